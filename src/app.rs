@@ -3,37 +3,146 @@
 // This code is licensed under MIT license (see LICENSE.txt for details)
 //
 
-use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::configuration::{Configuration, SwitchDirection};
+use anyhow::{anyhow, Context, Result};
+
+use crate::config_watcher::ConfigWatcher;
+use crate::configuration::{Configuration, StartupAction, SwitchDirection, UsbDetector};
+use crate::debounce::Debouncer;
 use crate::logging;
-use crate::platform::{wake_displays, PnPDetect};
-use crate::usb;
+use crate::platform::wake_displays;
+use crate::trigger::{self, TriggerCallback};
 use crate::{display_control, Args};
 
+/// How often the configuration file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct App {
     config: Configuration,
+    /// USB detector given on the command line, which overrides the configuration's
+    usb_detector: Option<UsbDetector>,
+    /// Profile given on the command line, which overrides the configuration's until the file's changes
+    profile: Option<String>,
+    /// The active profile set in the configuration file when it was last loaded
+    file_profile: Option<String>,
+    config_watcher: Option<ConfigWatcher>,
 }
 
-impl usb::UsbCallback for App {
-    #[allow(unused_must_use)]
-    fn device_added(&self, device_id: &str) {
-        debug!("Detected device change. Added device: {:?}", device_id);
-        if device_id == self.config.usb_device {
-            info!("Monitored device ({:?}) is connected", &self.config.usb_device);
-            std::thread::spawn(|| {
-                wake_displays().map_err(|err| error!("{:?}", err));
+enum Event {
+    InitialState(String, SwitchDirection),
+    StateUpdated(String, SwitchDirection),
+    /// A trigger source, by ID, has stopped, with the reason if it has failed
+    SourceStopped(usize, &'static str, Result<()>),
+    ConfigReloaded(Box<Configuration>),
+}
+
+/// Forwards trigger states from the trigger sources' threads to the app's event loop
+struct TriggerEventSender {
+    sender: Sender<Event>,
+    stop: Arc<AtomicBool>,
+}
+
+impl TriggerCallback for TriggerEventSender {
+    fn initial_state(&self, trigger: &str, state: SwitchDirection) {
+        // Only fails if the event loop is gone, and then there is no one to notify anyway
+        if !self.stopped() {
+            let _ = self.sender.send(Event::InitialState(trigger.to_owned(), state));
+        }
+    }
+
+    fn state_updated(&self, trigger: &str, state: SwitchDirection) {
+        if !self.stopped() {
+            let _ = self.sender.send(Event::StateUpdated(trigger.to_owned(), state));
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+/// A trigger source running in its own thread
+struct RunningSource {
+    /// Names of the triggers whose states it reports
+    triggers: Vec<String>,
+    /// Tells the source to stop, once its triggers' settings have changed
+    stop: Arc<AtomicBool>,
+}
+
+/// The running trigger sources, by ID. Sources are restarted once their triggers' settings have changed: the new
+/// sources are only started once the old ones have stopped, as they may use the same devices or files.
+struct TriggerSources {
+    running: HashMap<usize, RunningSource>,
+    next_id: usize,
+    /// Triggers whose sources are to be started, once no source is stopping
+    pending: BTreeSet<String>,
+}
+
+impl TriggerSources {
+    fn new(config: &Configuration) -> Self {
+        Self {
+            running: HashMap::new(),
+            next_id: 0,
+            pending: config.active_triggers().into_iter().map(|(name, _)| name).collect(),
+        }
+    }
+
+    /// Start the sources of the pending triggers, unless some sources are still stopping
+    fn start_pending(&mut self, config: &Configuration, sender: &Sender<Event>) {
+        if self.stopping() {
+            return;
+        }
+        let pending = std::mem::take(&mut self.pending);
+        for source in trigger::trigger_sources(config) {
+            let triggers = source.triggers();
+            if !triggers.iter().any(|trigger| pending.contains(trigger)) {
+                continue;
+            }
+            let id = self.next_id;
+            self.next_id += 1;
+            let stop = Arc::new(AtomicBool::new(false));
+            let callback = Arc::new(TriggerEventSender {
+                sender: sender.clone(),
+                stop: stop.clone(),
+            });
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let name = source.name();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| source.run(callback)))
+                    .unwrap_or_else(|_| Err(anyhow!("{} has crashed", name)));
+                let _ = sender.send(Event::SourceStopped(id, name, result));
             });
-            display_control::switch(&self.config, SwitchDirection::Connect);
+            self.running.insert(id, RunningSource { triggers, stop });
         }
     }
 
-    fn device_removed(&self, device_id: &str) {
-        debug!("Detected device change. Removed device: {:?}", device_id);
-        if device_id == self.config.usb_device {
-            info!("Monitored device is ({:?}) is disconnected", &self.config.usb_device);
-            display_control::switch(&self.config, SwitchDirection::Disconnect);
+    /// Stop the sources of the given triggers, and have them restarted along with the other triggers of these sources
+    fn restart(&mut self, triggers: BTreeSet<String>) {
+        for source in self.running.values() {
+            if source.triggers.iter().any(|trigger| triggers.contains(trigger)) {
+                source.stop.store(true, Ordering::SeqCst);
+                self.pending.extend(source.triggers.iter().cloned());
+            }
         }
+        self.pending.extend(triggers);
+    }
+
+    fn stopping(&self) -> bool {
+        self.running.values().any(|source| source.stop.load(Ordering::SeqCst))
+    }
+
+    /// Forget a source that has stopped, and return whether it was told to
+    fn stopped(&mut self, id: usize) -> bool {
+        self.running
+            .remove(&id)
+            .is_some_and(|source| source.stop.load(Ordering::SeqCst))
     }
 }
 
@@ -46,16 +155,174 @@ impl App {
             timestamp = env!("VERGEN_BUILD_TIMESTAMP"),
             git = env!("VERGEN_GIT_DESCRIBE"),
         );
-        let config = Configuration::load(args.config_file_path.as_deref()).context("failed to load configuration")?;
+        let config_path = match args.config_file_path {
+            Some(path) => path,
+            None => Configuration::config_file_name()?,
+        };
+        let (mut config, settings) =
+            Configuration::load_with_settings(&config_path).context("failed to load configuration")?;
+        if let Some(usb_detector) = args.usb_detector {
+            config.general.usb_detector = usb_detector;
+        }
+        let file_profile = config.general.active_profile.clone();
+        if let Some(profile) = &args.profile {
+            config.set_active_profile(Some(profile.clone()))?;
+        }
+        if let Some(profile) = &config.general.active_profile {
+            info!("Using profile {:?}", profile);
+        }
 
-        Ok(Self { config })
+        Ok(Self {
+            config,
+            usb_detector: args.usb_detector,
+            profile: args.profile,
+            file_profile,
+            config_watcher: Some(ConfigWatcher::new(config_path, settings)),
+        })
     }
 
-    pub fn run(self) -> Result<()> {
+    pub fn run(mut self) -> Result<()> {
         display_control::log_current_source();
-        let pnp_detector = PnPDetect::new(Box::new(self));
-        pnp_detector.detect()?;
+        let (sender, receiver) = mpsc::channel();
+        if let Some(mut config_watcher) = self.config_watcher.take() {
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                thread::sleep(CONFIG_POLL_INTERVAL);
+                if let Some(config) = config_watcher.poll() {
+                    if sender.send(Event::ConfigReloaded(Box::new(config))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        self.event_loop(sender, receiver)
+    }
 
-        Ok(())
+    /// Handle trigger events until all the trigger sources have failed
+    fn event_loop(&mut self, sender: Sender<Event>, receiver: Receiver<Event>) -> Result<()> {
+        // Triggers' states are only debounced once their state at startup is known
+        let mut debouncers: HashMap<String, Debouncer> = HashMap::new();
+        let mut sources = TriggerSources::new(&self.config);
+        sources.start_pending(&self.config, &sender);
+        loop {
+            let event = match debouncers.values().filter_map(Debouncer::deadline).min() {
+                Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                // A trigger whose source was restarted with another trigger's keeps its state, and only switches the
+                // displays if its state has changed meanwhile
+                Ok(Event::InitialState(trigger, state)) => match debouncers.get_mut(&trigger) {
+                    Some(debouncer) => debouncer.update(state, Instant::now()),
+                    None => {
+                        let mut debouncer =
+                            Debouncer::new(self.config.general.debounce, self.config.general.disconnect_delay);
+                        self.startup(&trigger, state, &mut debouncer);
+                        debouncers.insert(trigger, debouncer);
+                    }
+                },
+                Ok(Event::StateUpdated(trigger, state)) => {
+                    if let Some(debouncer) = debouncers.get_mut(&trigger) {
+                        debouncer.update(state, Instant::now());
+                    }
+                }
+                Ok(Event::SourceStopped(id, name, result)) => {
+                    let restarted = sources.stopped(id);
+                    // The other sources' triggers keep switching the displays
+                    match result {
+                        Ok(()) if restarted => debug!("{} has stopped", name),
+                        Ok(()) => error!("{} has stopped", name),
+                        Err(err) => error!("{} has failed: {:?}", name, err),
+                    }
+                    sources.start_pending(&self.config, &sender);
+                    if sources.running.is_empty() {
+                        return Err(anyhow!("all the trigger sources have stopped"));
+                    }
+                }
+                Ok(Event::ConfigReloaded(config)) => {
+                    let changed_triggers = self.reload(*config, &mut debouncers);
+                    if !changed_triggers.is_empty() {
+                        info!("Restarting the changed triggers: {:?}", changed_triggers);
+                        // Changed triggers are handled as new ones, according to the startup action
+                        debouncers.retain(|trigger, _| !changed_triggers.contains(trigger));
+                        sources.restart(changed_triggers);
+                        sources.start_pending(&self.config, &sender);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            let now = Instant::now();
+            for (trigger, debouncer) in debouncers.iter_mut() {
+                if let Some(direction) = debouncer.poll(now) {
+                    self.switch(trigger, direction);
+                }
+            }
+        }
+    }
+
+    /// Switch to a reloaded configuration, and return the triggers whose settings have changed
+    fn reload(&mut self, mut config: Configuration, debouncers: &mut HashMap<String, Debouncer>) -> BTreeSet<String> {
+        if let Some(usb_detector) = self.usb_detector {
+            config.general.usb_detector = usb_detector;
+        }
+        let file_profile = config.general.active_profile.clone();
+        // Changing the active profile in the file, e.g. with "set-profile", takes precedence over the command line
+        if self.profile.is_some() && file_profile != self.file_profile {
+            info!("The active profile was changed in the configuration file, the --profile option no longer applies");
+            self.profile = None;
+        }
+        self.file_profile = file_profile;
+        if let Some(profile) = &self.profile {
+            if let Err(err) = config.set_active_profile(Some(profile.clone())) {
+                error!(
+                    "Cannot use the reloaded configuration, keeping the current configuration: {:?}",
+                    err
+                );
+                return BTreeSet::new();
+            }
+        }
+        if config.general.active_profile != self.config.general.active_profile {
+            info!("Using profile {:?}", config.general.active_profile);
+        }
+        let changed_triggers = config.changed_triggers(&self.config);
+        for debouncer in debouncers.values_mut() {
+            debouncer.set_delays(config.general.debounce, config.general.disconnect_delay);
+        }
+        self.config = config;
+        changed_triggers
+    }
+
+    /// Handle a trigger's state at startup, according to the configured startup action. The state is only known once
+    /// the devices present at startup have all been reported, so it's acted upon immediately, without debouncing.
+    fn startup(&self, trigger: &str, state: SwitchDirection, debouncer: &mut Debouncer) {
+        debouncer.reset(state);
+        match (self.config.general.on_startup, state) {
+            (StartupAction::Connect, SwitchDirection::Connect)
+            | (StartupAction::Disconnect, SwitchDirection::Disconnect) => self.switch(trigger, state),
+            (StartupAction::Log, _) => info!(
+                "Trigger {:?} is {} at startup",
+                trigger,
+                match state {
+                    SwitchDirection::Connect => "connected",
+                    SwitchDirection::Disconnect => "disconnected",
+                }
+            ),
+            _ => {}
+        }
+    }
+
+    #[allow(unused_must_use)]
+    fn switch(&self, trigger: &str, direction: SwitchDirection) {
+        info!("Switching displays on {} of trigger {:?}", direction, trigger);
+        if let Some(rule) = self.config.rule_name(trigger) {
+            info!("Using rule {:?}", rule);
+        }
+        if direction == SwitchDirection::Connect {
+            thread::spawn(|| {
+                wake_displays().map_err(|err| error!("{:?}", err));
+            });
+        }
+        display_control::switch(&self.config.for_trigger(trigger), direction);
     }
 }
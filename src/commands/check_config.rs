@@ -0,0 +1,366 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::configuration::{monitor_number, Configuration, TriggerConfiguration};
+use crate::display_control;
+use crate::input_source::InputSource;
+use crate::usb::UsbDeviceMatcher;
+
+/// Problems found in a configuration
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// Whether an invalid value was found, which also fails loading the configuration
+    invalid_values: bool,
+}
+
+/// Where a setting is, e.g. "on_usb_connect" or "[monitor.left] on_usb_connect"
+fn location(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("[{}] {}", section, key),
+        None => key.to_owned(),
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+impl Report {
+    fn unknown_key(&mut self, section: Option<&str>, key: &str, known: &[&str]) {
+        let suggestion = known
+            .iter()
+            .map(|name| (edit_distance(key, name), name))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, name)| format!(", did you mean {:?}?", name))
+            .unwrap_or_default();
+        self.errors
+            .push(format!("{}: unknown setting{}", location(section, key), suggestion));
+    }
+
+    fn invalid_value(&mut self, section: Option<&str>, key: &str, err: impl std::fmt::Display) {
+        self.errors.push(format!("{}: {}", location(section, key), err));
+        self.invalid_values = true;
+    }
+
+    /// Check the values of the settings that are easy to get wrong
+    fn check_value(&mut self, section: Option<&str>, key: &str, value: &Value) {
+        match key {
+            "on_usb_connect" | "on_usb_disconnect" => {
+                if let Err(err) = InputSource::deserialize(value) {
+                    self.invalid_value(section, key, err);
+                }
+            }
+            "usb_device" => {
+                let devices = match value {
+                    Value::String(s) => s.split(',').map(str::to_owned).collect(),
+                    Value::Array(values) => values
+                        .iter()
+                        .map(|v| v.as_str().unwrap_or_default().to_owned())
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for device in devices.iter().filter(|device| !device.trim().is_empty()) {
+                    if let Err(err) = device.parse::<UsbDeviceMatcher>() {
+                        self.invalid_value(section, key, err);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_section(&mut self, section: &str, settings: &Value, known: &[&str]) {
+        for (key, value) in settings.as_object().into_iter().flatten() {
+            if known.contains(&key.as_str()) {
+                self.check_value(Some(section), key, value);
+            } else {
+                self.unknown_key(Some(section), key, known);
+            }
+        }
+    }
+
+    fn check_trigger(&mut self, name: &str, settings: &Value) {
+        let section = format!("trigger.{}", name);
+        let Some(trigger_type) = settings.get("type").and_then(Value::as_str) else {
+            // Reported when loading the configuration
+            return;
+        };
+        let Some(names) = TriggerConfiguration::setting_names(trigger_type) else {
+            self.invalid_value(
+                Some(&section),
+                "type",
+                format!("unknown trigger type {:?}", trigger_type),
+            );
+            return;
+        };
+        let known = std::iter::once("type").chain(names.iter().copied()).collect::<Vec<_>>();
+        self.check_section(&section, settings, &known);
+    }
+
+    /// Check a `[profile.NAME]` or `[rule.NAME]` section, and its per-monitor sections
+    fn check_layer(&mut self, section: &str, settings: &Value, known: &[&str]) {
+        let monitor_settings = Configuration::monitor_setting_names();
+        for (key, value) in settings.as_object().into_iter().flatten() {
+            match key.as_str() {
+                "monitor" => {
+                    for (name, monitor) in value.as_object().into_iter().flatten() {
+                        self.check_section(&format!("{}.monitor.{}", section, name), monitor, &monitor_settings);
+                    }
+                }
+                key if monitor_number(key).is_some() => {
+                    self.check_section(&format!("{}.{}", section, key), value, &monitor_settings)
+                }
+                key if known.contains(&key) => self.check_value(Some(section), key, value),
+                key => self.unknown_key(Some(section), key, known),
+            }
+        }
+    }
+
+    /// Check the settings one by one, to report all the problems at once and the settings that would be ignored
+    fn check_settings(&mut self, settings: &Value) {
+        let known = Configuration::setting_names();
+        let monitor_settings = Configuration::monitor_setting_names();
+        let profile_settings = Configuration::profile_setting_names();
+        let rule_settings = Configuration::rule_setting_names();
+        for (key, value) in settings.as_object().into_iter().flatten() {
+            match key.as_str() {
+                "trigger" => {
+                    for (name, trigger) in value.as_object().into_iter().flatten() {
+                        self.check_trigger(name, trigger);
+                    }
+                }
+                "monitor" => {
+                    for (name, monitor) in value.as_object().into_iter().flatten() {
+                        self.check_section(&format!("monitor.{}", name), monitor, &monitor_settings);
+                    }
+                }
+                key if monitor_number(key).is_some() => self.check_section(key, value, &monitor_settings),
+                "profile" => {
+                    for (name, profile) in value.as_object().into_iter().flatten() {
+                        self.check_layer(&format!("profile.{}", name), profile, &profile_settings);
+                    }
+                }
+                "rule" => {
+                    for (name, rule) in value.as_object().into_iter().flatten() {
+                        self.check_layer(&format!("rule.{}", name), rule, &rule_settings);
+                    }
+                }
+                key if known.contains(&key) => self.check_value(None, key, value),
+                key => self.unknown_key(None, key, &known),
+            }
+        }
+    }
+
+    /// Check that each per-monitor section is used for one of the displays
+    fn check_monitors(&mut self, config: &Configuration, display_names: &[String]) {
+        let monitor_ids = config.monitor_ids();
+        if monitor_ids.is_empty() {
+            return;
+        }
+        if display_names.is_empty() {
+            self.warnings
+                .push("no DDC-compatible displays detected, the monitor sections were not checked".into());
+            return;
+        }
+        let matching = display_names
+            .iter()
+            .map(|display_name| (display_name, config.matching_monitor_sections(display_name)))
+            .collect::<Vec<_>>();
+        for (name, monitor_id) in monitor_ids.iter() {
+            if matching.iter().any(|(_, sections)| sections.first() == Some(name)) {
+                continue;
+            }
+            match matching.iter().find(|(_, sections)| sections.contains(name)) {
+                Some((display_name, sections)) => self.warnings.push(format!(
+                    "monitor section {:?}: monitor_id {:?} matches {}, but is shadowed by section {:?}, which comes first",
+                    name, monitor_id, display_name, sections[0]
+                )),
+                None => self.warnings.push(format!(
+                    "monitor section {:?}: monitor_id {:?} matches none of the detected displays ({})",
+                    name,
+                    monitor_id,
+                    display_names.join(", ")
+                )),
+            }
+        }
+    }
+}
+
+/// Check a configuration file, print the problems found, and fail if there are errors
+pub fn check_config(path: Option<&Path>) -> Result<()> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => Configuration::config_file_name()?,
+    };
+    let settings: Value = Configuration::read_file(&path)
+        .with_context(|| format!("failed to read {:?}", path))?
+        .try_deserialize()?;
+    let source = Configuration::read(&path).with_context(|| format!("failed to read {:?}", path))?;
+
+    let mut report = Report::default();
+    report.check_settings(&settings);
+    match Configuration::from_source(source) {
+        Ok((config, _)) => report.check_monitors(&config, &display_control::display_names()),
+        // Invalid values also fail loading, and are reported already
+        Err(err) if !report.invalid_values => report.errors.push(format!("{:#}", err)),
+        Err(_) => {}
+    }
+
+    for warning in report.warnings.iter() {
+        println!("warning: {}", warning);
+    }
+    for error in report.errors.iter() {
+        println!("error: {}", error);
+    }
+    if !report.errors.is_empty() {
+        return Err(anyhow!("{} error(s) found in {:?}", report.errors.len(), path));
+    }
+    println!("{:?}: OK", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(config_str: &str, display_names: &[&str]) -> Report {
+        let source = config::Config::builder()
+            .add_source(config::File::from_str(config_str, config::FileFormat::Ini))
+            .build()
+            .unwrap();
+        let mut report = Report::default();
+        report.check_settings(&source.clone().try_deserialize().unwrap());
+        if let Ok((config, _)) = Configuration::from_source(source) {
+            let display_names = display_names
+                .iter()
+                .map(|name| format!("'{}'", name))
+                .collect::<Vec<_>>();
+            report.check_monitors(&config, &display_names);
+        }
+        report
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let report = check(
+            r#"
+            usb_device = "dead:beef"
+            on_usb_connect = "Hdmi1"
+
+            [monitor.left]
+            monitor_id = "len"
+            on_usb_disconnect = "0x0f"
+
+            [trigger.dock]
+            type = "network"
+            interface = "eth1"
+        "#,
+            &["LEN P27u"],
+        );
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let report = check(
+            r#"
+            usb_device = "dead:beef, 1050-0407"
+            on_usb_conect = "Hdmi1"
+            on_usb_disconnect = "Hdmi9"
+
+            [monitor1]
+            monitor_id = "len"
+            on_usb_connect_exec = "echo"
+
+            [trigger.dock]
+            type = "network"
+            interface = "eth1"
+            poll = "1s"
+
+            [profile.gaming]
+            on_usb_connect = "Hdmi3"
+
+            [profile.gaming.monitor.left]
+            monitorid = "len"
+
+            [rule.dock]
+            trigger = "dock"
+            on_usb_connect = "DisplayPort1"
+            on_usb_disconect = "Hdmi1"
+        "#,
+            &[],
+        );
+        assert_eq!(
+            report.errors,
+            vec![
+                "[monitor1] on_usb_connect_exec: unknown setting".to_owned(),
+                r#"on_usb_conect: unknown setting, did you mean "on_usb_connect"?"#.to_owned(),
+                "on_usb_disconnect: Invalid input source: hdmi9".to_owned(),
+                "[profile.gaming.monitor.left] monitorid: unknown setting, did you mean \"monitor_id\"?".to_owned(),
+                "[profile.gaming] on_usb_connect: Invalid input source: hdmi3".to_owned(),
+                "[rule.dock] on_usb_disconect: unknown setting, did you mean \"on_usb_disconnect\"?".to_owned(),
+                "[trigger.dock] poll: unknown setting".to_owned(),
+                r#"usb_device: Invalid USB device ID "1050-0407": expected a 4-digit hex vendor ID and product ID separated by a colon (e.g. "046d:c52b"), either can be "*""#.to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_monitors() {
+        let config = r#"
+            usb_device = "dead:beef"
+
+            [monitor1]
+            monitor_id = "len"
+
+            [monitor.right]
+            monitor_id = "dell"
+        "#;
+        let report = check(config, &["DELL U2723"]);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with(r#"monitor section "monitor1": monitor_id "len""#));
+        assert_eq!(check(config, &[]).warnings.len(), 1);
+
+        let report = check(
+            r#"
+            usb_device = "dead:beef"
+
+            [monitor.center]
+            monitor_id = "dell"
+
+            [monitor.left]
+            monitor_id = "dell u27"
+        "#,
+            &["DELL U2723"],
+        );
+        assert_eq!(
+            report.warnings,
+            vec![
+                r#"monitor section "left": monitor_id "dell u27" matches 'DELL U2723', but is shadowed by section "center", which comes first"#
+            ]
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::io;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::configuration::Configuration;
+
+/// Set a top-level setting in a configuration file, or remove it, keeping the rest of the file as it is
+pub fn set_global_setting(path: &Path, key: &str, value: Option<&str>) -> Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+    };
+    let format = Configuration::file_format(path);
+    let content = match format {
+        config::FileFormat::Ini | config::FileFormat::Toml => set_setting_line(&content, key, value),
+        config::FileFormat::Yaml => set_yaml_setting_line(&content, key, value),
+        config::FileFormat::Json => {
+            set_json_member(&content, key, value).with_context(|| format!("failed to edit {:?}", path))?
+        }
+        _ => return Err(anyhow!("cannot edit {:?}, set {} in it instead", path, key)),
+    };
+    check_setting(&content, format, key, value)
+        .with_context(|| format!("cannot edit {:?}, set {} in it instead", path, key))?;
+    std::fs::write(path, content).with_context(|| format!("failed to write {:?}", path))
+}
+
+/// Check that the edited text still parses, and has the setting as intended: the text is edited line by line, and
+/// settings written in an unexpected way can make the result invalid
+fn check_setting(content: &str, format: config::FileFormat, key: &str, value: Option<&str>) -> Result<()> {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(content, format))
+        .build()
+        .context("the edited file would be invalid")?;
+    let actual = config.get::<config::Value>(key).ok();
+    match (actual, value) {
+        (None, None) => Ok(()),
+        (Some(actual), Some(value)) if actual.clone().into_string().ok().as_deref() == Some(value) => Ok(()),
+        (actual, _) => Err(anyhow!(
+            "{} would be {} instead of {:?}",
+            key,
+            actual.map_or("set".to_owned(), |actual| actual.to_string()),
+            value
+        )),
+    }
+}
+
+/// A value quoted as a string: double-quoted INI, TOML and YAML strings are escaped the same as JSON strings
+fn quoted(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+/// How many lines the value of the setting on the first line spans: arrays and inline tables can span several
+fn value_line_count(lines: &[String]) -> usize {
+    let mut depth = 0;
+    for (index, line) in lines.iter().enumerate() {
+        let text = match index {
+            0 => line.split_once('=').map_or("", |(_, value)| value),
+            _ => line,
+        };
+        let mut quote = None;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some('"'), '\\') => {
+                    chars.next();
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '[' | '{') => depth += 1,
+                (None, ']' | '}') => depth -= 1,
+                (None, '#' | ';') => break,
+                _ => {}
+            }
+        }
+        if depth <= 0 {
+            return index + 1;
+        }
+    }
+    lines.len()
+}
+
+/// Replace the lines of a top-level setting, add one if there is none, or remove it if there is no value
+fn set_setting_line(content: &str, key: &str, value: Option<&str>) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_owned).collect();
+    // Global settings are the ones before the first section
+    let global_end = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..global_end]
+        .iter()
+        .position(|line| line.split_once('=').is_some_and(|(k, _)| k.trim() == key));
+    let line = value.map(|value| format!("{} = {}", key, quoted(value)));
+    match (existing, line) {
+        (Some(index), line) => {
+            let count = value_line_count(&lines[index..global_end]);
+            lines.splice(index..index + count, line);
+        }
+        (None, Some(line)) => lines.insert(0, line),
+        (None, None) => {}
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Same as `set_setting_line`, for YAML files: their top-level settings are the "key: value" lines that are not
+/// indented, followed by the indented lines or list items of their value, if any
+fn set_yaml_setting_line(content: &str, key: &str, value: Option<&str>) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_owned).collect();
+    let existing = lines
+        .iter()
+        .position(|line| line.split_once(':').is_some_and(|(k, _)| k.trim_end() == key));
+    let line = value.map(|value| format!("{}: {}", key, quoted(value)));
+    match (existing, line) {
+        (Some(index), line) => {
+            let count = 1 + lines[index + 1..]
+                .iter()
+                .take_while(|line| line.starts_with([' ', '\t']) || line.starts_with("- ") || line.as_str() == "-")
+                .count();
+            lines.splice(index..index + count, line);
+        }
+        (None, Some(line)) => {
+            // After the document start marker, if any
+            let index = usize::from(lines.first().is_some_and(|line| line.trim_end() == "---"));
+            lines.insert(index, line);
+        }
+        (None, None) => {}
+    }
+    lines.join("\n") + "\n"
+}
+
+/// A member of a JSON object: its key, and where it starts and ends in the text
+struct JsonMember {
+    key: String,
+    start: usize,
+    end: usize,
+}
+
+/// The top-level members of a JSON object, and where the members start (right after the opening brace)
+fn json_members(content: &str) -> Result<(usize, Vec<JsonMember>)> {
+    let bytes = content.as_bytes();
+    let skip_whitespace = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    // Where the string starting at `i` ends, after its closing quote
+    let skip_string = |mut i: usize| {
+        i += 1;
+        while i < bytes.len() && bytes[i] != b'"' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        i + 1
+    };
+    let open = skip_whitespace(0);
+    if bytes.get(open) != Some(&b'{') {
+        return Err(anyhow!("expected a JSON object"));
+    }
+    let mut members = Vec::new();
+    let mut i = skip_whitespace(open + 1);
+    while bytes.get(i) == Some(&b'"') {
+        let start = i;
+        i = skip_string(i);
+        let key = serde_json::from_str(&content[start..i.min(bytes.len())])?;
+        // The value ends at the next comma or closing brace that is not nested in it
+        let mut depth = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'"' => i = skip_string(i) - 1,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth > 0 => depth -= 1,
+                b',' | b'}' if depth == 0 => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        i = i.min(bytes.len());
+        let end = content[..i].trim_end().len();
+        members.push(JsonMember { key, start, end });
+        if bytes.get(i) == Some(&b',') {
+            i = skip_whitespace(i + 1);
+        }
+    }
+    Ok((open + 1, members))
+}
+
+/// Set a top-level member of a JSON object, or remove it, keeping the rest of the text as it is
+fn set_json_member(content: &str, key: &str, value: Option<&str>) -> Result<String> {
+    let (members_start, members) = json_members(content)?;
+    let member = value.map(|value| format!("{}: {}", serde_json::Value::from(key), serde_json::Value::from(value)));
+    let existing = members.iter().position(|m| m.key == key);
+    let mut content = content.to_owned();
+    match (existing, member) {
+        (Some(index), Some(member)) => content.replace_range(members[index].start..members[index].end, &member),
+        (None, Some(member)) => match members.first() {
+            // Indented the same as the first member
+            Some(first) => {
+                let indent = content[members_start..first.start].to_owned();
+                content.insert_str(first.start, &format!("{},{}", member, indent))
+            }
+            None => content.insert_str(members_start, &member),
+        },
+        (Some(index), None) => {
+            let range = match (members.get(index + 1), index.checked_sub(1)) {
+                (Some(next), _) => members[index].start..next.start,
+                (None, Some(previous)) => members[previous].end..members[index].end,
+                (None, None) => members[index].start..members[index].end,
+            };
+            content.replace_range(range, "");
+        }
+        (None, None) => {}
+    }
+    serde_json::from_str::<serde_json::Value>(&content).context("the edited file is not valid JSON")?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_setting_line() {
+        assert_eq!(
+            set_setting_line("", "usb_device", Some("046d:c52b")),
+            "usb_device = \"046d:c52b\"\n"
+        );
+        assert_eq!(
+            set_setting_line(
+                "on_usb_connect = Hdmi1\nusb_device=\"1050:0407\"\n\n[monitor1]\nmonitor_id = \"len\"\n",
+                "usb_device",
+                Some("046d:c52b")
+            ),
+            "on_usb_connect = Hdmi1\nusb_device = \"046d:c52b\"\n\n[monitor1]\nmonitor_id = \"len\"\n"
+        );
+        // A "usb_device" in a section is not the global setting
+        assert_eq!(
+            set_setting_line(
+                "on_usb_connect = Hdmi1\n[trigger]\nusb_device = \"1050:0407\"",
+                "usb_device",
+                Some("046d:c52b")
+            ),
+            "usb_device = \"046d:c52b\"\non_usb_connect = Hdmi1\n[trigger]\nusb_device = \"1050:0407\"\n"
+        );
+        assert_eq!(
+            set_setting_line(
+                "active_profile = \"work\"\non_usb_connect = Hdmi1\n",
+                "active_profile",
+                None
+            ),
+            "on_usb_connect = Hdmi1\n"
+        );
+    }
+
+    #[test]
+    fn test_set_yaml_setting_line() {
+        let yaml = "usb_device: \"dead:beef\"\nmonitor:\n  left:\n    active_profile: x\n";
+        assert_eq!(
+            set_yaml_setting_line(yaml, "active_profile", Some("work")),
+            format!("active_profile: \"work\"\n{}", yaml)
+        );
+        assert_eq!(
+            set_yaml_setting_line("---\nactive_profile: home\n", "active_profile", Some("work")),
+            "---\nactive_profile: \"work\"\n"
+        );
+        assert_eq!(
+            set_yaml_setting_line("active_profile: home\nusb_device: x\n", "active_profile", None),
+            "usb_device: x\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_values() {
+        let toml = "usb_device = [\n  \"046d:c52b\", # receiver\n  \"1050:0407\",\n]\non_usb_connect = \"Hdmi1\"\n";
+        assert_eq!(
+            set_setting_line(toml, "usb_device", Some("dead:beef")),
+            "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi1\"\n"
+        );
+        assert_eq!(
+            set_setting_line(toml, "usb_device", None),
+            "on_usb_connect = \"Hdmi1\"\n"
+        );
+        for yaml in [
+            "usb_device:\n  - \"046d:c52b\"\n  - \"1050:0407\"\non_usb_connect: Hdmi1\n",
+            "usb_device:\n- \"046d:c52b\"\n- \"1050:0407\"\non_usb_connect: Hdmi1\n",
+        ] {
+            assert_eq!(
+                set_yaml_setting_line(yaml, "usb_device", Some("dead:beef")),
+                "usb_device: \"dead:beef\"\non_usb_connect: Hdmi1\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_setting() {
+        use config::FileFormat::{Ini, Toml, Yaml};
+
+        let edited = set_setting_line("", "active_profile", Some("a \"b\" \\c"));
+        assert!(check_setting(&edited, Toml, "active_profile", Some("a \"b\" \\c")).is_ok());
+        assert!(check_setting(&edited, Ini, "active_profile", Some("a \"b\" \\c")).is_ok());
+        let edited = set_yaml_setting_line("", "active_profile", Some("a: \"b\""));
+        assert!(check_setting(&edited, Yaml, "active_profile", Some("a: \"b\"")).is_ok());
+
+        // A multi-line string is not recognized, and its other lines are left behind
+        let toml = "usb_device = \"\"\"\n046d:c52b\n\"\"\"\n";
+        let edited = set_setting_line(toml, "usb_device", Some("dead:beef"));
+        assert!(check_setting(&edited, Toml, "usb_device", Some("dead:beef")).is_err());
+        assert!(check_setting("usb_device = \"x\"\n", Toml, "usb_device", None).is_err());
+        assert!(check_setting("usb_device = [\"x\"]\n", Toml, "usb_device", Some("x")).is_err());
+    }
+
+    #[test]
+    fn test_set_json_member() {
+        let json = "{\n  \"usb_device\": \"dead:beef\",\n  \"profile\": {\"work\": {\"active_profile\": 1}}\n}\n";
+        let with_profile = set_json_member(json, "active_profile", Some("work")).unwrap();
+        assert_eq!(
+            with_profile,
+            "{\n  \"active_profile\": \"work\",\n  \"usb_device\": \"dead:beef\",\n  \
+             \"profile\": {\"work\": {\"active_profile\": 1}}\n}\n"
+        );
+        let changed = set_json_member(&with_profile, "active_profile", Some("a \"b\"")).unwrap();
+        assert!(changed.contains(r#""active_profile": "a \"b\"","#), "{}", changed);
+        assert_eq!(set_json_member(&with_profile, "active_profile", None).unwrap(), json);
+        assert_eq!(
+            set_json_member("{\"a\": [1, 2], \"active_profile\": \"x\"}", "active_profile", None).unwrap(),
+            "{\"a\": [1, 2]}"
+        );
+        assert_eq!(
+            set_json_member("{}", "active_profile", Some("work")).unwrap(),
+            "{\"active_profile\": \"work\"}"
+        );
+        assert!(set_json_member("[]", "active_profile", Some("work")).is_err());
+    }
+}
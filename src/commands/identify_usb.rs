@@ -0,0 +1,126 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::commands::edit_config::set_global_setting;
+use crate::configuration::Configuration;
+use crate::usb::{self, UsbDevice};
+
+/// Devices by ID and port: descriptor strings are not part of the key, as reading them can fail intermittently
+type DeviceSet = HashMap<(String, Option<String>), UsbDevice>;
+
+fn read_device_set() -> Result<DeviceSet> {
+    Ok(usb::connected_devices()?
+        .into_iter()
+        .map(|device| ((device.id.clone(), device.port_path.clone()), device))
+        .collect())
+}
+
+fn prompt(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(anyhow!("no answer, stopping"));
+    }
+    Ok(answer.trim().to_owned())
+}
+
+/// Find out which USB devices appear or disappear when the USB switch is pressed, and offer to write the
+/// chosen one into the configuration file
+pub fn identify_usb(config_file_path: Option<&Path>) -> Result<()> {
+    let before = read_device_set()?;
+    println!("Found {} USB devices.", before.len());
+    prompt("Press the button on your USB switch, wait a few seconds, then press Enter: ")?;
+    let after = read_device_set()?;
+
+    let mut candidates: Vec<(&str, &UsbDevice)> = Vec::new();
+    for (key, device) in after.iter() {
+        if !before.contains_key(key) {
+            candidates.push(("appeared", device));
+        }
+    }
+    for (key, device) in before.iter() {
+        if !after.contains_key(key) {
+            candidates.push(("disappeared", device));
+        }
+    }
+    if candidates.is_empty() {
+        println!("No USB devices have appeared or disappeared.");
+        return Ok(());
+    }
+    candidates.sort_by(|(_, a), (_, b)| a.port_path.cmp(&b.port_path).then_with(|| a.id.cmp(&b.id)));
+    for (index, (change, device)) in candidates.iter().enumerate() {
+        println!("{:>3}: {} {}", index + 1, change, device);
+    }
+
+    let answer = prompt(&format!(
+        "Which device should be monitored? [1-{}, Enter to skip]: ",
+        candidates.len()
+    ))?;
+    if answer.is_empty() {
+        return Ok(());
+    }
+    let (_, device) = answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|index| candidates.get(index.checked_sub(1)?))
+        .ok_or_else(|| anyhow!("Invalid choice: {:?}", answer))?;
+
+    let config_file_path = match config_file_path {
+        Some(path) => path.to_path_buf(),
+        None => Configuration::config_file_name()?,
+    };
+    let answer = prompt(&format!(
+        "Set usb_device = \"{}\" in {:?}? [y/N]: ",
+        device.id, config_file_path
+    ))?;
+    if !answer.eq_ignore_ascii_case("y") {
+        println!(
+            "Add usb_device = \"{}\" to the configuration to monitor this device.",
+            device.id
+        );
+        return Ok(());
+    }
+    set_global_setting(&config_file_path, "usb_device", Some(&device.id))?;
+    println!("Configuration updated.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_usb_device_list() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for (file_name, content) in [
+            (
+                "display-switch.yaml",
+                "usb_device:\n  - \"1050:0407\"\n  - \"046d:c52b\"\non_usb_connect: Hdmi1\n",
+            ),
+            (
+                "display-switch.toml",
+                "usb_device = [\n  \"1050:0407\",\n  \"046d:c52b\",\n]\non_usb_connect = \"Hdmi1\"\n",
+            ),
+        ] {
+            let path = temp_dir.path().join(file_name);
+            std::fs::write(&path, content).unwrap();
+            set_global_setting(&path, "usb_device", Some("dead:beef")).unwrap();
+            let (config, _) = Configuration::load_with_settings(&path).unwrap();
+            assert_eq!(
+                config.usb.usb_device,
+                vec!["dead:beef".parse().unwrap()],
+                "{}",
+                file_name
+            );
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use anyhow::Result;
+
+use crate::usb::{self, UsbDevice};
+
+/// Print the connected USB devices, with the IDs that can be used in the configuration
+pub fn list_usb(json: bool) -> Result<()> {
+    let devices = usb::connected_devices()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+    } else {
+        print_table(&devices);
+    }
+    Ok(())
+}
+
+fn print_table(devices: &[UsbDevice]) {
+    let rows = devices
+        .iter()
+        .map(|device| {
+            [
+                device.id.as_str(),
+                device.port_path.as_deref().unwrap_or("-"),
+                device.manufacturer.as_deref().unwrap_or("-"),
+                device.product.as_deref().unwrap_or("-"),
+                device.serial_number.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["ID", "PORT", "MANUFACTURER", "PRODUCT", "SERIAL"];
+    let mut widths = header.map(str::len);
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
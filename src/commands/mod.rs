@@ -0,0 +1,15 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+mod check_config;
+mod edit_config;
+mod identify_usb;
+mod list_usb;
+mod set_profile;
+
+pub use check_config::check_config;
+pub use identify_usb::identify_usb;
+pub use list_usb::list_usb;
+pub use set_profile::set_profile;
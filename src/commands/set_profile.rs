@@ -0,0 +1,28 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::commands::edit_config::set_global_setting;
+use crate::configuration::Configuration;
+
+/// Make a profile the active one in the configuration file, or use the top-level settings only. A running
+/// display-switch switches to it when it reloads the file.
+pub fn set_profile(config_file_path: Option<&Path>, profile: Option<&str>) -> Result<()> {
+    let path = match config_file_path {
+        Some(path) => path.to_path_buf(),
+        None => Configuration::config_file_name()?,
+    };
+    let (mut config, _) = Configuration::from_source(Configuration::read(&path)?)?;
+    config.set_active_profile(profile.map(str::to_owned))?;
+    set_global_setting(&path, "active_profile", profile)?;
+    match profile {
+        Some(profile) => println!("Profile {:?} is now active.", profile),
+        None => println!("No profile is active anymore."),
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::configuration::{diff_settings, Configuration, Settings};
+
+/// When a file was last modified, and its size
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watches the configuration file for changes, by polling its modification time and size rather than with file
+/// system notifications. The file is only reloaded once it has stopped changing for a poll interval, so that a file
+/// still being written is not loaded half-way.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// The settings of the configuration in use
+    settings: Settings,
+    /// The file's stamp when it was last loaded
+    loaded: Stamp,
+    /// The file's stamp when it was seen changed, if it hasn't been loaded since
+    changed: Option<Stamp>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, settings: Settings) -> Self {
+        let loaded = stamp(&path);
+        Self {
+            path,
+            settings,
+            loaded,
+            changed: None,
+        }
+    }
+
+    /// Check the file for changes, and return the new configuration if it has changed and is valid. An invalid
+    /// configuration is logged, and the one in use is kept.
+    pub fn poll(&mut self) -> Option<Configuration> {
+        let stamp = stamp(&self.path);
+        if stamp == self.loaded {
+            self.changed = None;
+            return None;
+        }
+        if self.changed != Some(stamp) {
+            debug!("Configuration file {:?} has changed", self.path);
+            self.changed = Some(stamp);
+            return None;
+        }
+        self.loaded = stamp;
+        self.changed = None;
+
+        let (config, settings) = match Configuration::load_with_settings(&self.path) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!(
+                    "Failed to reload the configuration from {:?}, keeping the current configuration: {:?}",
+                    self.path, err
+                );
+                return None;
+            }
+        };
+        let changes = diff_settings(&self.settings, &settings);
+        if changes.is_empty() {
+            info!("Configuration file {:?} was saved without changes", self.path);
+            return None;
+        }
+        info!("Configuration reloaded from {:?}, changes:", self.path);
+        for change in changes {
+            info!("  {}", change);
+        }
+        self.settings = settings;
+        Some(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("display-switch.ini");
+        fs::write(&path, "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi1\"\n").unwrap();
+        let (_, settings) = Configuration::load_with_settings(&path).unwrap();
+        let mut watcher = ConfigWatcher::new(path.clone(), settings);
+        assert!(watcher.poll().is_none());
+
+        // Only reloaded once the file has stopped changing
+        fs::write(&path, "usb_device = \"dead:beef\"\non_usb_connect = \"DisplayPort1\"\n").unwrap();
+        assert!(watcher.poll().is_none());
+        let config = watcher.poll().unwrap();
+        assert_eq!(config.default_input_sources.on_usb_connect.unwrap().value(), 0x0f);
+        assert!(watcher.poll().is_none());
+
+        // An invalid file is not loaded, and not retried until it changes again
+        fs::write(&path, "usb_device = \"dead-beef\"\n").unwrap();
+        assert!(watcher.poll().is_none());
+        assert!(watcher.poll().is_none());
+        assert!(watcher.poll().is_none());
+        fs::write(&path, "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi2\"\n").unwrap();
+        assert!(watcher.poll().is_none());
+        let config = watcher.poll().unwrap();
+        assert_eq!(config.default_input_sources.on_usb_connect.unwrap().value(), 0x12);
+    }
+}
@@ -4,17 +4,64 @@
 //
 
 use crate::input_source::InputSource;
+use crate::usb::{UsbDevice, UsbDeviceMatcher};
 use anyhow::{anyhow, Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::de::{IgnoredAny, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::time::Duration;
 
-#[derive(Debug, Copy, Clone)]
+/// Name of the trigger configured by the top-level USB settings
+pub const USB_TRIGGER: &str = "usb";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SwitchDirection {
     Connect,
     Disconnect,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// How multiple monitored USB devices are combined into a single connect/disconnect state
+#[derive(Debug, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsbDeviceMode {
+    /// "Connected" when any of the devices is present, "disconnected" when the last one is removed
+    #[default]
+    Any,
+    /// "Connected" when all of the devices are present, "disconnected" when the first one is removed
+    All,
+}
+
+/// What to do with the monitored devices' state when the app starts
+#[derive(Debug, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupAction {
+    /// Run the "connect" actions if the monitored devices are present
+    #[default]
+    Connect,
+    /// Run the "disconnect" actions if the monitored devices are absent
+    Disconnect,
+    /// Only log whether the monitored devices are present
+    Log,
+    /// Do nothing
+    Ignore,
+}
+
+/// How USB devices being plugged in / removed are detected
+#[derive(Debug, Deserialize, Copy, Clone, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UsbDetector {
+    /// libusb hotplug events on Linux and MacOS, device change notifications on Windows
+    #[default]
+    Default,
+    /// Kernel uevents on a netlink socket, Linux only
+    Uevent,
+    /// Polling the USB devices in sysfs, Linux only
+    Sysfs,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct InputSources {
     // Note: Serde alias won't work here, because of https://github.com/serde-rs/serde/issues/1504
     // So cannot alias "on_usb_connect" to "monitor_input"
@@ -24,25 +71,416 @@ pub struct InputSources {
     pub on_usb_disconnect_execute: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// How a monitor section's `monitor_id` is compared with the monitors' IDs, always ignoring case
+#[derive(Debug, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MonitorIdMatch {
+    /// The whole monitor ID
+    Exact,
+    /// Any part of the monitor ID
+    #[default]
+    Substring,
+    /// The whole monitor ID, with `*` matching any characters and `?` any single character
+    Glob,
+    /// A regular expression found anywhere in the monitor ID
+    Regex,
+}
+
+/// A `[monitorN]` or `[monitor.NAME]` section, as written in the configuration file
+#[derive(Deserialize)]
+struct PerMonitorSettings {
+    monitor_id: String,
+    #[serde(default, rename = "match")]
+    match_mode: MonitorIdMatch,
+    #[serde(flatten)]
+    input_sources: InputSources,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "PerMonitorSettings")]
 struct PerMonitorConfiguration {
     monitor_id: String,
+    match_mode: MonitorIdMatch,
+    /// The compiled pattern of the "glob" and "regex" modes
+    pattern: Option<Regex>,
+    input_sources: InputSources,
+}
+
+/// A regular expression matching the same monitor IDs as a glob
+fn glob_to_regex(glob: &str) -> String {
+    let pattern = glob
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_owned(),
+            '?' => ".".to_owned(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect::<String>();
+    format!("^{}$", pattern)
+}
+
+impl TryFrom<PerMonitorSettings> for PerMonitorConfiguration {
+    type Error = anyhow::Error;
+
+    fn try_from(settings: PerMonitorSettings) -> Result<Self> {
+        let pattern = match settings.match_mode {
+            MonitorIdMatch::Exact | MonitorIdMatch::Substring => None,
+            MonitorIdMatch::Glob => Some(glob_to_regex(&settings.monitor_id)),
+            MonitorIdMatch::Regex => Some(settings.monitor_id.clone()),
+        };
+        let pattern = pattern
+            .map(|pattern| RegexBuilder::new(&pattern).case_insensitive(true).build())
+            .transpose()
+            .map_err(|err| anyhow!("invalid monitor_id {:?}: {}", settings.monitor_id, err))?;
+        Ok(Self {
+            monitor_id: settings.monitor_id,
+            match_mode: settings.match_mode,
+            pattern,
+            input_sources: settings.input_sources,
+        })
+    }
+}
+
+/// `[monitorN]` sections, by number
+#[derive(Debug, Clone, Default)]
+struct NumberedMonitors(BTreeMap<u32, PerMonitorConfiguration>);
+
+/// `[monitor.NAME]` sections
+#[derive(Debug, Deserialize, Clone, Default)]
+struct NamedMonitors {
+    #[serde(default)]
+    monitor: BTreeMap<String, PerMonitorConfiguration>,
+}
+
+/// The per-monitor sections
+#[derive(Debug, Deserialize, Clone, Default)]
+struct MonitorSections {
+    #[serde(flatten)]
+    named: NamedMonitors,
+    #[serde(flatten)]
+    numbered: NumberedMonitors,
+}
+
+/// A `[profile.NAME]` or `[rule.NAME]` section: while it applies, its inputs and commands take precedence over the
+/// top-level ones, and its per-monitor sections are matched before the top-level ones
+#[derive(Debug, Deserialize, Clone)]
+pub struct Profile {
     #[serde(flatten)]
     input_sources: InputSources,
+    #[serde(flatten)]
+    monitors: MonitorSections,
+}
+
+/// A `[rule.NAME]` section: settings used when a given trigger switches the displays, e.g. different inputs for
+/// different USB devices
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rule {
+    /// Name of the trigger
+    trigger: String,
+    #[serde(flatten)]
+    settings: Profile,
+}
+
+/// Settings of a "usb" trigger: which USB devices are monitored
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct UsbTriggerConfiguration {
+    #[serde(default, deserialize_with = "deserialize_usb_device")]
+    pub usb_device: Vec<UsbDeviceMatcher>,
+    #[serde(default)]
+    pub usb_device_mode: UsbDeviceMode,
+    /// Optional serial number (iSerialNumber) the monitored device must have, matched exactly
+    pub usb_serial: Option<String>,
+    /// Optional manufacturer name (iManufacturer) the monitored device must have, case-insensitive
+    pub usb_manufacturer: Option<String>,
+    /// Optional product name (iProduct) the monitored device must have, case-insensitive
+    pub usb_product: Option<String>,
+    /// Optional physical port the monitored device must be plugged into, e.g. "1-3.2"
+    pub usb_port_path: Option<String>,
+    /// The monitored device is a hub: it's connected and disconnected together with the devices plugged into it,
+    /// so these are ignored
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub usb_hub_subtree: bool,
+}
+
+/// Settings of a "network" trigger: "connect" when a network interface's link is up
+#[cfg(target_os = "linux")]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct NetworkTriggerConfiguration {
+    /// Name of the network interface, e.g. "enx00e04c680001"
+    pub interface: String,
+    /// How often the interface's state is polled
+    #[serde(
+        default = "Configuration::default_poll_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub poll_interval: Duration,
+}
+
+/// Settings of a "drm" trigger: "connect" when a display is connected to a video output
+#[cfg(target_os = "linux")]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct DrmTriggerConfiguration {
+    /// Name of the DRM connector, e.g. "DP-1" or "card0-HDMI-A-1"
+    pub connector: String,
+    /// How often the connector's status is polled
+    #[serde(
+        default = "Configuration::default_poll_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub poll_interval: Duration,
+}
+
+/// Settings of a "bluetooth" trigger: "connect" when a Bluetooth device is connected
+#[cfg(target_os = "linux")]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct BluetoothTriggerConfiguration {
+    /// The device's address, e.g. "AA:BB:CC:DD:EE:FF"
+    pub address: String,
+    /// Address of the D-Bus bus BlueZ is on, the system bus by default
+    pub dbus_address: Option<String>,
+}
+
+/// How a "file" trigger's path is used
+#[cfg(target_os = "linux")]
+#[derive(Debug, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileTriggerMode {
+    /// "Connected" while the file exists
+    #[default]
+    Flag,
+    /// A named pipe, "connect" and "disconnect" lines written to it switch the displays
+    Fifo,
+}
+
+/// Settings of a "file" trigger, for driving the switching from scripts
+#[cfg(target_os = "linux")]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct FileTriggerConfiguration {
+    pub path: std::path::PathBuf,
+    #[serde(default)]
+    pub mode: FileTriggerMode,
+}
+
+/// A named source of connect / disconnect events, configured in a `[trigger.NAME]` section
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TriggerConfiguration {
+    Usb(UsbTriggerConfiguration),
+    #[cfg(target_os = "linux")]
+    Network(NetworkTriggerConfiguration),
+    #[cfg(target_os = "linux")]
+    Drm(DrmTriggerConfiguration),
+    #[cfg(target_os = "linux")]
+    Bluetooth(BluetoothTriggerConfiguration),
+    #[cfg(target_os = "linux")]
+    File(FileTriggerConfiguration),
+}
+
+/// The top-level settings that are neither USB trigger, input nor per-monitor settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct GeneralSettings {
+    /// Named triggers
+    #[serde(default)]
+    pub trigger: BTreeMap<String, TriggerConfiguration>,
+    /// Names of the triggers that switch the displays, all the configured triggers by default
+    #[serde(default, deserialize_with = "deserialize_list")]
+    pub triggers: Vec<String>,
+    /// Device events are coalesced until the monitored devices' state is stable for this long
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub debounce: Duration,
+    /// Disconnect actions only run if the monitored devices stay disconnected for this long
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub disconnect_delay: Duration,
+    #[serde(default)]
+    pub on_startup: StartupAction,
+    #[serde(default)]
+    pub usb_detector: UsbDetector,
+    /// Where sysfs is mounted, used by the "uevent" and "sysfs" USB detectors
+    #[serde(default = "Configuration::default_sysfs_root")]
+    pub sysfs_root: std::path::PathBuf,
+    /// How often the "sysfs" USB detector polls for changes
+    #[serde(
+        default = "Configuration::default_usb_poll_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub usb_poll_interval: Duration,
+    /// Named profiles
+    #[serde(default)]
+    profile: BTreeMap<String, Profile>,
+    /// Name of the profile in use, if any
+    pub active_profile: Option<String>,
+    /// Rules, by name
+    #[serde(default)]
+    rule: BTreeMap<String, Rule>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct Configuration {
-    #[serde(deserialize_with = "Configuration::deserialize_usb_device")]
-    pub usb_device: String,
+    /// The implicit "usb" trigger, configured by the top-level USB settings
+    #[serde(flatten)]
+    pub usb: UsbTriggerConfiguration,
+    #[serde(flatten)]
+    pub general: GeneralSettings,
     #[serde(flatten)]
     pub default_input_sources: InputSources,
-    monitor1: Option<PerMonitorConfiguration>,
-    monitor2: Option<PerMonitorConfiguration>,
-    monitor3: Option<PerMonitorConfiguration>,
-    monitor4: Option<PerMonitorConfiguration>,
-    monitor5: Option<PerMonitorConfiguration>,
-    monitor6: Option<PerMonitorConfiguration>,
+    #[serde(flatten)]
+    monitors: MonitorSections,
+    /// The trigger the displays are switched for, whose rule applies
+    #[serde(skip)]
+    switching_trigger: Option<String>,
+}
+
+/// Parses a duration such as "500ms", "5s" or "1m"
+fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split_at = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split_at);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("Invalid duration {:?}: expected e.g. \"500ms\" or \"5s\"", s))?;
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "" if value == 0 => Ok(Duration::ZERO),
+        _ => Err(anyhow!(
+            "Invalid duration {:?}: expected a unit of \"ms\", \"s\" or \"m\", e.g. \"500ms\" or \"5s\"",
+            s
+        )),
+    }
+}
+
+/// Accepts either a single value, a comma-separated list of values, or a list of values
+fn deserialize_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let values = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => s.split(',').map(str::to_owned).collect(),
+        OneOrMany::Many(v) => v,
+    };
+    let mut unique_values: Vec<String> = Vec::new();
+    for value in values.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        if !unique_values.iter().any(|v| v == value) {
+            unique_values.push(value.to_owned());
+        }
+    }
+    Ok(unique_values)
+}
+
+fn deserialize_usb_device<'de, D>(deserializer: D) -> Result<Vec<UsbDeviceMatcher>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut devices: Vec<UsbDeviceMatcher> = Vec::new();
+    for device in deserialize_list(deserializer)? {
+        let device = device.parse().map_err(serde::de::Error::custom)?;
+        if !devices.contains(&device) {
+            devices.push(device);
+        }
+    }
+    if devices.is_empty() {
+        return Err(serde::de::Error::custom("usb_device must list at least one device"));
+    }
+    Ok(devices)
+}
+
+/// Accepts booleans given as strings, as values in flattened structures are not converted by the config crate
+fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(value) => Ok(value),
+        BoolOrString::String(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| serde::de::Error::custom(format!("Invalid boolean {:?}: expected \"true\" or \"false\"", s))),
+    }
+}
+
+/// The number of a `[monitorN]` section
+pub fn monitor_number(section: &str) -> Option<u32> {
+    section
+        .strip_prefix("monitor")
+        .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|n| n.parse().ok())
+}
+
+/// Picks the `[monitorN]` sections out of the top-level settings
+impl<'de> Deserialize<'de> for NumberedMonitors {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NumberedMonitorsVisitor;
+
+        impl<'de> Visitor<'de> for NumberedMonitorsVisitor {
+            type Value = NumberedMonitors;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "[monitorN] sections")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut monitors = BTreeMap::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    let Some(number) = monitor_number(&key) else {
+                        map.next_value::<IgnoredAny>()?;
+                        continue;
+                    };
+                    let config = map
+                        .next_value()
+                        .map_err(|err| serde::de::Error::custom(format!("invalid section [{}]: {}", key, err)))?;
+                    if monitors.insert(number, config).is_some() {
+                        return Err(serde::de::Error::custom(format!(
+                            "section [{}] duplicates [monitor{}]",
+                            key, number
+                        )));
+                    }
+                }
+                Ok(NumberedMonitors(monitors))
+            }
+        }
+
+        deserializer.deserialize_map(NumberedMonitorsVisitor)
+    }
+}
+
+/// Accepts integers as well, from formats that have them: only 0 is valid without a unit
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrString {
+        Int(u64),
+        String(String),
+    }
+
+    let s = match IntOrString::deserialize(deserializer)? {
+        IntOrString::Int(value) => value.to_string(),
+        IntOrString::String(s) => s,
+    };
+    parse_duration(&s).map_err(serde::de::Error::custom)
 }
 
 impl fmt::Display for SwitchDirection {
@@ -54,22 +492,278 @@ impl fmt::Display for SwitchDirection {
     }
 }
 
+/// "Connect" when the trigger's condition is met, e.g. a device is connected or a file exists
+impl From<bool> for SwitchDirection {
+    fn from(connected: bool) -> Self {
+        if connected {
+            Self::Connect
+        } else {
+            Self::Disconnect
+        }
+    }
+}
+
+impl UsbDeviceMode {
+    /// Whether the monitored devices should be considered "connected", given how many of them are present
+    pub fn is_connected(&self, present: usize, total: usize) -> bool {
+        match self {
+            Self::Any => present > 0,
+            Self::All => present > 0 && present >= total,
+        }
+    }
+}
+
+impl UsbTriggerConfiguration {
+    fn is_empty(&self) -> bool {
+        self.usb_device.is_empty()
+            && self.usb_serial.is_none()
+            && self.usb_manufacturer.is_none()
+            && self.usb_product.is_none()
+            && self.usb_port_path.is_none()
+            && !self.usb_hub_subtree
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.usb_device.is_empty() && self.usb_port_path.is_none() {
+            return Err(anyhow!("either usb_device or usb_port_path has to be configured"));
+        }
+        Ok(())
+    }
+
+    /// Whether a given USB device is one of the monitored devices
+    pub fn matches_usb_device(&self, device: &UsbDevice) -> bool {
+        fn matches(expected: &Option<String>, actual: &Option<String>, eq: fn(&str, &str) -> bool) -> bool {
+            match (expected, actual) {
+                (None, _) => true,
+                (Some(expected), Some(actual)) => eq(expected.trim(), actual),
+                (Some(_), None) => false,
+            }
+        }
+
+        (self.usb_device.is_empty() || self.usb_device.iter().any(|matcher| matcher.matches_id(&device.id)))
+            && matches(&self.usb_port_path, &device.port_path, |a, b| a == b)
+            && matches(&self.usb_serial, &device.serial_number, |a, b| a == b)
+            && matches(&self.usb_manufacturer, &device.manufacturer, |a, b| {
+                a.eq_ignore_ascii_case(b)
+            })
+            && matches(&self.usb_product, &device.product, |a, b| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Whether the monitored devices are considered "connected", given the IDs of the matching devices present.
+    /// Every configured `usb_device` entry counts once, no matter how many present devices it matches.
+    pub fn is_usb_connected(&self, present_ids: &[&str]) -> bool {
+        if self.usb_device.is_empty() {
+            return !present_ids.is_empty();
+        }
+        let present = self
+            .usb_device
+            .iter()
+            .filter(|matcher| present_ids.iter().any(|id| matcher.matches_id(id)))
+            .count();
+        self.usb_device_mode.is_connected(present, self.usb_device.len())
+    }
+}
+
+/// Check the name of the sysfs entry a polling trigger reads, e.g. "network interface": a single path component
+#[cfg(target_os = "linux")]
+fn validate_sysfs_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        return Err(anyhow!("invalid {} name {:?}", kind, name));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn validate_poll_interval(poll_interval: Duration) -> Result<()> {
+    if poll_interval.is_zero() {
+        return Err(anyhow!("poll_interval has to be greater than zero"));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl NetworkTriggerConfiguration {
+    fn validate(&self) -> Result<()> {
+        validate_sysfs_name("network interface", &self.interface)?;
+        validate_poll_interval(self.poll_interval)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl DrmTriggerConfiguration {
+    fn validate(&self) -> Result<()> {
+        validate_sysfs_name("DRM connector", &self.connector)?;
+        validate_poll_interval(self.poll_interval)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl BluetoothTriggerConfiguration {
+    fn validate(&self) -> Result<()> {
+        let parts = self.address.split(':').collect::<Vec<&str>>();
+        if parts.len() != 6
+            || !parts
+                .iter()
+                .all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(anyhow!(
+                "Invalid Bluetooth address {:?}: expected six hex bytes separated by colons, e.g. \"AA:BB:CC:DD:EE:FF\"",
+                self.address
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FileTriggerConfiguration {
+    fn validate(&self) -> Result<()> {
+        if self.path.file_name().is_none() {
+            return Err(anyhow!("invalid trigger file path {:?}", self.path));
+        }
+        Ok(())
+    }
+}
+
+impl TriggerConfiguration {
+    fn validate(&self) -> Result<()> {
+        match self {
+            Self::Usb(usb) => usb.validate(),
+            #[cfg(target_os = "linux")]
+            Self::Network(network) => network.validate(),
+            #[cfg(target_os = "linux")]
+            Self::Drm(drm) => drm.validate(),
+            #[cfg(target_os = "linux")]
+            Self::Bluetooth(bluetooth) => bluetooth.validate(),
+            #[cfg(target_os = "linux")]
+            Self::File(file) => file.validate(),
+        }
+    }
+}
+
+/// Error of `FieldNamesDeserializer`, carrying the names of the fields of the struct it was asked to deserialize
+#[derive(Debug)]
+struct FieldNames(&'static [&'static str]);
+
+impl fmt::Display for FieldNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fields {:?}", self.0)
+    }
+}
+
+impl std::error::Error for FieldNames {}
+
+impl serde::de::Error for FieldNames {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        FieldNames(&[])
+    }
+}
+
+/// A deserializer that only finds out which fields a struct has
+struct FieldNamesDeserializer;
+
+impl<'de> Deserializer<'de> for FieldNamesDeserializer {
+    type Error = FieldNames;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, FieldNames> {
+        Err(FieldNames(&[]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, FieldNames> {
+        Err(FieldNames(fields))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Names of the settings of a struct. Structs with flattened fields have none: they are deserialized as maps.
+fn struct_fields<T: serde::de::DeserializeOwned>() -> &'static [&'static str] {
+    match T::deserialize(FieldNamesDeserializer) {
+        Err(FieldNames(fields)) => fields,
+        Ok(_) => &[],
+    }
+}
+
+impl TriggerConfiguration {
+    /// Names of the settings of a type of trigger, besides "type", or None if there is no such type
+    pub fn setting_names(trigger_type: &str) -> Option<&'static [&'static str]> {
+        match trigger_type {
+            "usb" => Some(struct_fields::<UsbTriggerConfiguration>()),
+            #[cfg(target_os = "linux")]
+            "network" => Some(struct_fields::<NetworkTriggerConfiguration>()),
+            #[cfg(target_os = "linux")]
+            "drm" => Some(struct_fields::<DrmTriggerConfiguration>()),
+            #[cfg(target_os = "linux")]
+            "bluetooth" => Some(struct_fields::<BluetoothTriggerConfiguration>()),
+            #[cfg(target_os = "linux")]
+            "file" => Some(struct_fields::<FileTriggerConfiguration>()),
+            _ => None,
+        }
+    }
+}
+
+impl MonitorSections {
+    fn validate(&self) -> Result<()> {
+        for number in self.numbered.0.keys() {
+            let name = format!("monitor{}", number);
+            if self.named.monitor.contains_key(&name) {
+                return Err(anyhow!("sections [monitor.{}] and [{}] have the same name", name, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The sections by name: the `[monitorN]` sections in numeric order, then the `[monitor.NAME]` sections in
+    /// alphabetical order
+    fn iter(&self) -> impl Iterator<Item = (String, &PerMonitorConfiguration)> {
+        let numbered = self
+            .numbered
+            .0
+            .iter()
+            .map(|(number, config)| (format!("monitor{}", number), config));
+        numbered.chain(self.named.monitor.iter().map(|(name, config)| (name.clone(), config)))
+    }
+}
+
 impl PerMonitorConfiguration {
     fn matches(&self, monitor_id: &str) -> bool {
-        monitor_id.to_lowercase().contains(&self.monitor_id.to_lowercase())
+        match (&self.pattern, self.match_mode) {
+            (Some(pattern), _) => pattern.is_match(monitor_id),
+            (None, MonitorIdMatch::Exact) => monitor_id.to_lowercase() == self.monitor_id.to_lowercase(),
+            (None, _) => monitor_id.to_lowercase().contains(&self.monitor_id.to_lowercase()),
+        }
     }
 }
 
+/// Whether merged input sources inherit the `*_execute` commands of the ones they are merged with
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Commands {
+    Inherited,
+    /// Used for the per-monitor settings: the global commands are run once per switch, separately from the
+    /// per-monitor ones, otherwise with two monitors the same command would run twice
+    NotInherited,
+}
+
 impl InputSources {
-    fn merge(&self, default: &Self) -> Self {
+    /// These settings, or the default ones where these are not set
+    fn merge(&self, default: &Self, commands: Commands) -> Self {
+        let command = |own: &Option<String>, default: &Option<String>| match commands {
+            Commands::Inherited => own.clone().or_else(|| default.clone()),
+            Commands::NotInherited => own.clone(),
+        };
         Self {
             on_usb_connect: self.on_usb_connect.or(default.on_usb_connect),
             on_usb_disconnect: self.on_usb_disconnect.or(default.on_usb_disconnect),
-            // Global configuration for execution is not merged! Otherwise, for two
-            // monitors, we'll be executing the same command twice. Global config is treated
-            // separately during switching.
-            on_usb_connect_execute: self.on_usb_connect_execute.clone(),
-            on_usb_disconnect_execute: self.on_usb_disconnect_execute.clone(),
+            on_usb_connect_execute: command(&self.on_usb_connect_execute, &default.on_usb_connect_execute),
+            on_usb_disconnect_execute: command(&self.on_usb_disconnect_execute, &default.on_usb_disconnect_execute),
         }
     }
 
@@ -88,28 +782,335 @@ impl InputSources {
     }
 }
 
+/// The settings a configuration was loaded from, as "key = value" pairs: keys in sections are prefixed with the
+/// section's name, e.g. "monitor.left.monitor_id"
+pub type Settings = BTreeMap<String, String>;
+
+fn flatten_settings(prefix: &str, value: &serde_json::Value, settings: &mut Settings) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_settings(&key, value, settings);
+            }
+        }
+        value => {
+            settings.insert(prefix.to_owned(), value.to_string());
+        }
+    }
+}
+
+/// The settings that differ between two configurations, one line per setting
+pub fn diff_settings(old: &Settings, new: &Settings) -> Vec<String> {
+    let keys = old.keys().chain(new.keys()).collect::<std::collections::BTreeSet<_>>();
+    keys.into_iter()
+        .filter_map(|key| match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) if old == new => None,
+            (Some(old), Some(new)) => Some(format!("{}: {} -> {}", key, old, new)),
+            (Some(old), None) => Some(format!("{}: {} -> (unset)", key, old)),
+            (None, Some(new)) => Some(format!("{}: (unset) -> {}", key, new)),
+            (None, None) => None,
+        })
+        .collect()
+}
+
 impl Configuration {
-    pub fn load(path: Option<&std::path::Path>) -> Result<Self> {
-        let path = match path {
-            Some(p) => p.to_path_buf(),
-            None => Self::config_file_name()?,
-        };
+    /// Load the configuration, along with the settings it was loaded from
+    pub fn load_with_settings(path: &std::path::Path) -> Result<(Self, Settings)> {
+        let (config, settings) = Self::from_source(Self::read(path)?)?;
+        info!("Configuration loaded ({:?}): {:?}", path, config);
+        Ok((config, settings))
+    }
 
+    /// Read the configuration file and the environment's overrides, without interpreting the settings
+    pub fn read(path: &std::path::Path) -> Result<config::Config> {
         let builder = config::Config::builder()
-            .add_source(config::File::from(path.as_path()))
+            .add_source(config::File::from(path).format(Self::file_format(path)))
             .add_source(config::Environment::with_prefix("DISPLAY_SWITCH"));
+        Ok(builder.build()?)
+    }
 
-        let config: Self = builder.build()?.try_deserialize()?;
-        info!("Configuration loaded ({:?}): {:?}", path, config);
-        Ok(config)
+    /// Read the configuration file only, without the environment: `DISPLAY_SWITCH_*` variables that are not
+    /// settings, such as `DISPLAY_SWITCH_CONFIG_FILE`, would show up as unknown settings
+    pub fn read_file(path: &std::path::Path) -> Result<config::Config> {
+        let builder = config::Config::builder().add_source(config::File::from(path).format(Self::file_format(path)));
+        Ok(builder.build()?)
     }
 
-    fn deserialize_usb_device<'de, D>(deserializer: D) -> Result<String, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        Ok(s.to_lowercase())
+    /// Interpret and validate the settings that were read
+    pub fn from_source(source: config::Config) -> Result<(Self, Settings)> {
+        let mut settings = Settings::new();
+        flatten_settings("", &source.clone().try_deserialize()?, &mut settings);
+        let config: Self = source.try_deserialize()?;
+        config.validate()?;
+        Ok((config, settings))
+    }
+
+    /// Names of the top-level settings, besides the `[monitorN]` sections
+    pub fn setting_names() -> Vec<&'static str> {
+        [
+            struct_fields::<GeneralSettings>(),
+            struct_fields::<NamedMonitors>(),
+            struct_fields::<UsbTriggerConfiguration>(),
+            struct_fields::<InputSources>(),
+        ]
+        .concat()
+    }
+
+    /// Names of the settings of `[monitorN]` and `[monitor.NAME]` sections
+    pub fn monitor_setting_names() -> Vec<&'static str> {
+        ["monitor_id", "match"]
+            .into_iter()
+            .chain(struct_fields::<InputSources>().iter().copied())
+            .collect()
+    }
+
+    /// Names of the settings of `[profile.NAME]` sections, besides the per-monitor sections
+    pub fn profile_setting_names() -> Vec<&'static str> {
+        [struct_fields::<NamedMonitors>(), struct_fields::<InputSources>()].concat()
+    }
+
+    /// Names of the settings of `[rule.NAME]` sections, besides the per-monitor sections
+    pub fn rule_setting_names() -> Vec<&'static str> {
+        std::iter::once("trigger")
+            .chain(Self::profile_setting_names())
+            .collect()
+    }
+
+    /// The per-monitor sections' names and monitor IDs
+    pub fn monitor_ids(&self) -> Vec<(String, &str)> {
+        self.monitors()
+            .map(|(name, config)| (name, config.monitor_id.as_str()))
+            .collect()
+    }
+
+    /// Names of the active triggers set up differently in both configurations, including those active in only one of
+    /// them. Triggers are only configured when they are started, and need to be restarted when they have changed.
+    pub fn changed_triggers(&self, other: &Self) -> BTreeSet<String> {
+        let sysfs_changed = self.general.sysfs_root != other.general.sysfs_root;
+        let usb_detector_changed = sysfs_changed
+            || self.general.usb_detector != other.general.usb_detector
+            || self.general.usb_poll_interval != other.general.usb_poll_interval;
+        let changed =
+            |trigger: &(String, TriggerConfiguration), others: &[(String, TriggerConfiguration)]| match trigger {
+                (_, TriggerConfiguration::Usb(_)) if usb_detector_changed => true,
+                #[cfg(target_os = "linux")]
+                (_, TriggerConfiguration::Network(_) | TriggerConfiguration::Drm(_)) if sysfs_changed => true,
+                _ => !others.contains(trigger),
+            };
+        let (triggers, other_triggers) = (self.active_triggers(), other.active_triggers());
+        triggers
+            .iter()
+            .filter(|trigger| changed(trigger, &other_triggers))
+            .chain(other_triggers.iter().filter(|trigger| changed(trigger, &triggers)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The format of a configuration file, by its extension: files without a known extension are INI
+    pub fn file_format(path: &std::path::Path) -> config::FileFormat {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("toml") => config::FileFormat::Toml,
+            Some("yaml") | Some("yml") => config::FileFormat::Yaml,
+            Some("json") => config::FileFormat::Json,
+            _ => config::FileFormat::Ini,
+        }
+    }
+
+    fn default_sysfs_root() -> std::path::PathBuf {
+        std::path::PathBuf::from("/sys")
+    }
+
+    fn default_usb_poll_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// How often the state of the polling triggers, e.g. "network" and "drm", is read
+    #[cfg(target_os = "linux")]
+    fn default_poll_interval() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn validate(&self) -> Result<()> {
+        // Without any named triggers, the top-level USB settings are required
+        if !self.usb.is_empty() || self.general.trigger.is_empty() {
+            self.usb.validate()?;
+            if self.general.trigger.contains_key(USB_TRIGGER) {
+                return Err(anyhow!(
+                    "trigger {:?} is already configured by the top-level USB settings",
+                    USB_TRIGGER
+                ));
+            }
+        }
+        for (name, trigger) in self.general.trigger.iter() {
+            trigger
+                .validate()
+                .with_context(|| format!("invalid trigger {:?}", name))?;
+        }
+        let triggers = self.all_triggers();
+        for name in self.general.triggers.iter() {
+            if !triggers.iter().any(|(trigger_name, _)| trigger_name == name) {
+                return Err(anyhow!("triggers refers to an unknown trigger {:?}", name));
+            }
+        }
+        if self.general.usb_poll_interval.is_zero() {
+            return Err(anyhow!("usb_poll_interval has to be greater than zero"));
+        }
+        self.monitors.validate()?;
+        for (name, profile) in self.general.profile.iter() {
+            profile
+                .monitors
+                .validate()
+                .with_context(|| format!("invalid profile {:?}", name))?;
+        }
+        if let Some(name) = &self.general.active_profile {
+            self.profile(name)?;
+        }
+        for (name, rule) in self.general.rule.iter() {
+            if !triggers.iter().any(|(trigger_name, _)| *trigger_name == rule.trigger) {
+                return Err(anyhow!(
+                    "rule {:?} refers to an unknown trigger {:?}",
+                    name,
+                    rule.trigger
+                ));
+            }
+            if let Some((other, _)) = self
+                .general
+                .rule
+                .iter()
+                .find(|(other, other_rule)| *other < name && other_rule.trigger == rule.trigger)
+            {
+                return Err(anyhow!(
+                    "rules {:?} and {:?} are both for trigger {:?}",
+                    other,
+                    name,
+                    rule.trigger
+                ));
+            }
+            rule.settings
+                .monitors
+                .validate()
+                .with_context(|| format!("invalid rule {:?}", name))?;
+        }
+        Ok(())
+    }
+
+    /// Name of the rule for a trigger, if there is one
+    pub fn rule_name(&self, trigger: &str) -> Option<&str> {
+        self.general
+            .rule
+            .iter()
+            .find(|(_, rule)| rule.trigger == trigger)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The configuration to switch the displays with for a trigger: the trigger's rule applies, if it has one
+    pub fn for_trigger(&self, trigger: &str) -> Self {
+        Self {
+            switching_trigger: Some(trigger.to_owned()),
+            ..self.clone()
+        }
+    }
+
+    /// The rule and profile that apply, by name, in order of precedence
+    fn layers(&self) -> Vec<(String, &Profile)> {
+        let rule = self
+            .switching_trigger
+            .as_deref()
+            .and_then(|trigger| self.rule_name(trigger))
+            .map(|name| (format!("rule.{}", name), &self.general.rule[name].settings));
+        let profile = self
+            .general
+            .active_profile
+            .as_ref()
+            .and_then(|name| Some((format!("profile.{}", name), self.general.profile.get(name)?)));
+        rule.into_iter().chain(profile).collect()
+    }
+
+    fn profile(&self, name: &str) -> Result<&Profile> {
+        self.general.profile.get(name).ok_or_else(|| {
+            anyhow!(
+                "unknown profile {:?}, the profiles are: {:?}",
+                name,
+                self.general.profile.keys().collect::<Vec<_>>()
+            )
+        })
+    }
+
+    /// Make a profile the active one, or use the top-level settings only
+    pub fn set_active_profile(&mut self, name: Option<String>) -> Result<()> {
+        if let Some(name) = &name {
+            self.profile(name)?;
+        }
+        self.general.active_profile = name;
+        Ok(())
+    }
+
+    /// The top-level inputs and commands, overridden by the applying rule's and the active profile's
+    pub fn input_sources(&self) -> InputSources {
+        self.layers()
+            .iter()
+            .rev()
+            .fold(self.default_input_sources.clone(), |input_sources, (_, layer)| {
+                layer.input_sources.merge(&input_sources, Commands::Inherited)
+            })
+    }
+
+    /// The per-monitor sections, by name: the applying rule's and the active profile's first, then the top-level ones
+    fn monitors(&self) -> impl Iterator<Item = (String, &PerMonitorConfiguration)> {
+        let layer_monitors = self.layers().into_iter().flat_map(|(layer, settings)| {
+            settings
+                .monitors
+                .iter()
+                .map(move |(name, config)| (format!("{}.{}", layer, name), config))
+        });
+        layer_monitors.chain(self.monitors.iter())
+    }
+
+    /// Name of the first per-monitor section matching the monitor, if any
+    pub fn monitor_section(&self, monitor_id: &str) -> Option<String> {
+        self.monitors()
+            .find(|(_, config)| config.matches(monitor_id))
+            .map(|(name, _)| name)
+    }
+
+    /// Names of all the per-monitor sections matching the monitor, the one that is used first
+    pub fn matching_monitor_sections(&self, monitor_id: &str) -> Vec<String> {
+        self.monitors()
+            .filter(|(_, config)| config.matches(monitor_id))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// All the configured triggers, by name
+    fn all_triggers(&self) -> Vec<(String, TriggerConfiguration)> {
+        let usb = Some((USB_TRIGGER.to_owned(), TriggerConfiguration::Usb(self.usb.clone())))
+            .filter(|_| !self.usb.is_empty());
+        usb.into_iter()
+            .chain(
+                self.general
+                    .trigger
+                    .iter()
+                    .map(|(name, trigger)| (name.clone(), trigger.clone())),
+            )
+            .collect()
+    }
+
+    /// The triggers that switch the displays
+    pub fn active_triggers(&self) -> Vec<(String, TriggerConfiguration)> {
+        self.all_triggers()
+            .into_iter()
+            .filter(|(name, _)| self.general.triggers.is_empty() || self.general.triggers.contains(name))
+            .collect()
     }
 
     pub fn config_file_name() -> Result<std::path::PathBuf> {
@@ -125,7 +1126,13 @@ impl Configuration {
         };
         std::fs::create_dir_all(&config_dir)
             .with_context(|| format!("failed to create directory: {:?}", config_dir))?;
-        Ok(config_dir.join("display-switch.ini"))
+        // The first existing file of any of the supported formats, INI being the default
+        let config_file = ["ini", "toml", "yaml", "yml", "json"]
+            .iter()
+            .map(|ext| config_dir.join(format!("display-switch.{}", ext)))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| config_dir.join("display-switch.ini"));
+        Ok(config_file)
     }
 
     pub fn log_file_name() -> Result<std::path::PathBuf> {
@@ -146,63 +1153,531 @@ impl Configuration {
 
     pub fn configuration_for_monitor(&self, monitor_id: &str) -> InputSources {
         // Find a matching per-monitor config, if there is any
-        let per_monitor_config = [
-            &self.monitor1,
-            &self.monitor2,
-            &self.monitor3,
-            &self.monitor4,
-            &self.monitor5,
-            &self.monitor6,
-        ]
-        .iter()
-        .find_map(|config| {
-            config
-                .as_ref()
-                .and_then(|config| if config.matches(monitor_id) { Some(config) } else { None })
-        });
+        let per_monitor_config = self
+            .monitors()
+            .map(|(_, config)| config)
+            .find(|config| config.matches(monitor_id))
+            .map_or_else(InputSources::default, |config| config.input_sources.clone());
         // Merge global config as needed
-        per_monitor_config.map_or(
-            InputSources {
-                on_usb_connect: self.default_input_sources.on_usb_connect,
-                on_usb_disconnect: self.default_input_sources.on_usb_disconnect,
-                on_usb_connect_execute: None,
-                on_usb_disconnect_execute: None,
-            },
-            |config| config.input_sources.merge(&self.default_input_sources),
+        per_monitor_config.merge(&self.input_sources(), Commands::NotInherited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ConfigError;
+    use config::FileFormat::{self, Ini, Json, Toml, Yaml};
+
+    #[test]
+    fn test_log_file_name() {
+        let file_name = Configuration::log_file_name();
+        assert!(file_name.is_ok());
+        assert!(file_name.unwrap().ends_with("display-switch.log"))
+    }
+
+    fn load_config_str(config_str: &str, format: FileFormat) -> Result<Configuration, ConfigError> {
+        config::Config::builder()
+            .add_source(config::File::from_str(config_str, format))
+            .build()?
+            .try_deserialize()
+    }
+
+    fn to_yaml(value: &serde_json::Value) -> yaml_rust2::Yaml {
+        use yaml_rust2::Yaml;
+        match value {
+            serde_json::Value::Object(map) => {
+                Yaml::Hash(map.iter().map(|(k, v)| (Yaml::String(k.clone()), to_yaml(v))).collect())
+            }
+            serde_json::Value::Array(values) => Yaml::Array(values.iter().map(to_yaml).collect()),
+            serde_json::Value::String(s) => Yaml::String(s.clone()),
+            other => Yaml::from_str(&other.to_string()),
+        }
+    }
+
+    /// Loads a test configuration written as INI, after checking that the same configuration converted to each of
+    /// the other supported formats loads the same way
+    fn load_test_config(config_str: &str) -> Result<Configuration, ConfigError> {
+        let config = load_config_str(config_str, Ini);
+        let tree = config::Config::builder()
+            .add_source(config::File::from_str(config_str, Ini))
+            .build()
+            .and_then(|c| c.try_deserialize::<serde_json::Value>());
+        // INI that doesn't parse cannot be converted
+        let Ok(tree) = tree else {
+            return config;
+        };
+        let mut yaml = String::new();
+        yaml_rust2::YamlEmitter::new(&mut yaml).dump(&to_yaml(&tree)).unwrap();
+        for (format, converted) in [
+            (Toml, toml::to_string(&tree).unwrap()),
+            (Yaml, yaml),
+            (Json, tree.to_string()),
+        ] {
+            assert_eq!(
+                format!("{:?}", load_config_str(&converted, format).ok()),
+                format!("{:?}", config.as_ref().ok()),
+                "configuration converted to {:?}:\n{}",
+                format,
+                converted
+            );
+        }
+        config
+    }
+
+    #[test]
+    fn test_usb_device_deserialization() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+            on_usb_connect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.usb.usb_device, vec!["dead:beef".parse().unwrap()]);
+        assert_eq!(config.usb.usb_device_mode, UsbDeviceMode::Any);
+        assert_eq!(config.general.usb_detector, UsbDetector::Default);
+    }
+
+    #[test]
+    fn test_startup_action_deserialization() {
+        let config = load_test_config(r#"usb_device = "dead:beef""#).unwrap();
+        assert_eq!(config.general.on_startup, StartupAction::Connect);
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:beef"
+            on_startup = "log"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.general.on_startup, StartupAction::Log);
+    }
+
+    #[test]
+    fn test_usb_detector_deserialization() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+            usb_detector = "uevent"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.general.usb_detector, UsbDetector::Uevent);
+        assert_eq!(config.general.sysfs_root, std::path::PathBuf::from("/sys"));
+        assert!(load_test_config(r#"usb_detector = "udev""#).is_err());
+
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+            usb_detector = "sysfs"
+            sysfs_root = "/tmp/fake-sys"
+            usb_poll_interval = "250ms"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.general.usb_detector, UsbDetector::Sysfs);
+        assert_eq!(config.general.sysfs_root, std::path::PathBuf::from("/tmp/fake-sys"));
+        assert_eq!(config.general.usb_poll_interval, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_multiple_usb_devices_deserialization() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF, 1050:0407"
+            usb_device_mode = "all"
+            on_usb_connect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.usb.usb_device,
+            vec!["dead:beef".parse().unwrap(), "1050:0407".parse().unwrap()]
+        );
+        assert_eq!(config.usb.usb_device_mode, UsbDeviceMode::All);
+        assert!(!config.usb.is_usb_connected(&["dead:beef"]));
+        assert!(config.usb.is_usb_connected(&["dead:beef", "1050:0407"]));
+    }
+
+    #[test]
+    fn test_invalid_usb_device_deserialization() {
+        let err = load_test_config(r#"usb_device = "1050-0407""#).unwrap_err();
+        assert!(err.to_string().contains("1050-0407"), "{}", err);
+        assert!(load_test_config(r#"usb_device = "1050:407""#).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_usb_device_deserialization() {
+        let config = load_test_config(r#"usb_device = "046d:*""#).unwrap();
+        let device = UsbDevice {
+            id: "046d:c52b".into(),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            port_path: None,
+            devpath: None,
+        };
+        assert!(config.usb.matches_usb_device(&device));
+        assert!(!config.usb.matches_usb_device(&UsbDevice {
+            id: "1050:0407".into(),
+            ..device
+        }));
+    }
+
+    #[test]
+    fn test_usb_device_descriptor_matching() {
+        let config = load_test_config(
+            r#"
+            usb_device = "046d:c52b"
+            usb_serial = "ABC123"
+            usb_product = "usb receiver"
+            on_usb_connect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        let device = UsbDevice {
+            id: "046d:c52b".into(),
+            manufacturer: Some("Logitech".into()),
+            product: Some("USB Receiver".into()),
+            serial_number: Some("ABC123".into()),
+            port_path: Some("1-3.2".into()),
+            devpath: None,
+        };
+        assert!(config.usb.matches_usb_device(&device));
+        assert!(!config.usb.matches_usb_device(&UsbDevice {
+            serial_number: Some("XYZ789".into()),
+            ..device.clone()
+        }));
+        assert!(!config.usb.matches_usb_device(&UsbDevice {
+            serial_number: None,
+            ..device.clone()
+        }));
+        assert!(!config.usb.matches_usb_device(&UsbDevice {
+            id: "046d:c077".into(),
+            ..device
+        }));
+    }
+
+    #[test]
+    fn test_usb_port_path_matching() {
+        let config = load_test_config(
+            r#"
+            usb_port_path = "1-3.2"
+            on_usb_connect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let device = UsbDevice {
+            id: "046d:c52b".into(),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            port_path: Some("1-3.2".into()),
+            devpath: None,
+        };
+        assert!(config.usb.matches_usb_device(&device));
+        assert!(config.usb.matches_usb_device(&UsbDevice {
+            id: "1050:0407".into(),
+            ..device.clone()
+        }));
+        assert!(!config.usb.matches_usb_device(&UsbDevice {
+            port_path: Some("1-3.1".into()),
+            ..device
+        }));
+    }
+
+    #[test]
+    fn test_usb_hub_subtree_deserialization() {
+        let config = load_test_config(r#"usb_device = "05e3:0610""#).unwrap();
+        assert!(!config.usb.usb_hub_subtree);
+        let config = load_test_config(
+            r#"
+            usb_device = "05e3:0610"
+            usb_hub_subtree = true
+        "#,
+        )
+        .unwrap();
+        assert!(config.usb.usb_hub_subtree);
+        assert!(load_test_config(
+            r#"
+            usb_device = "05e3:0610"
+            usb_hub_subtree = maybe
+        "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_usb_device_required() {
+        let config = load_test_config(r#"on_usb_connect = "DisplayPort2""#).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_named_triggers() {
+        let config = load_test_config(
+            r#"
+            usb_device = "046d:c52b"
+            triggers = "usb, yubikey"
+
+            [trigger.yubikey]
+            type = "usb"
+            usb_device = "1050:0407"
+            usb_serial = "ABC123"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let names = |triggers: Vec<(String, TriggerConfiguration)>| {
+            triggers.into_iter().map(|(name, _)| name).collect::<Vec<String>>()
+        };
+        assert_eq!(names(config.all_triggers()), vec!["usb", "dock", "yubikey"]);
+        assert_eq!(names(config.active_triggers()), vec!["usb", "yubikey"]);
+        let TriggerConfiguration::Usb(yubikey) = &config.general.trigger["yubikey"] else {
+            panic!("unexpected trigger type");
+        };
+        assert_eq!(yubikey.usb_device, vec!["1050:0407".parse().unwrap()]);
+        assert_eq!(yubikey.usb_serial.as_deref(), Some("ABC123"));
+
+        // Named triggers replace the top-level USB settings
+        let config = load_test_config(
+            r#"
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(names(config.active_triggers()), vec!["dock"]);
+    }
+
+    #[test]
+    fn test_changed_triggers() {
+        let config = |config_str: &str| load_test_config(config_str).unwrap();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<BTreeSet<String>>();
+        let triggers = config(
+            r#"
+            usb_device = "046d:c52b"
+            on_usb_connect = "Hdmi1"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        );
+        let inputs_changed = config(
+            r#"
+            usb_device = "046d:c52b"
+            on_usb_connect = "DisplayPort1"
+            debounce = "100ms"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        );
+        assert!(triggers.changed_triggers(&inputs_changed).is_empty());
+        let device_changed = config(
+            r#"
+            usb_device = "1050:0407"
+            on_usb_connect = "Hdmi1"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        );
+        assert_eq!(triggers.changed_triggers(&device_changed), names(&["usb"]));
+        let detector_changed = config(
+            r#"
+            usb_device = "046d:c52b"
+            on_usb_connect = "Hdmi1"
+            usb_poll_interval = "5s"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        );
+        assert_eq!(triggers.changed_triggers(&detector_changed), names(&["dock", "usb"]));
+
+        // Only the active triggers are started
+        let trigger_deactivated = config(
+            r#"
+            usb_device = "046d:c52b"
+            on_usb_connect = "Hdmi1"
+            triggers = "usb"
+
+            [trigger.dock]
+            type = "usb"
+            usb_port_path = "1-3.2"
+        "#,
+        );
+        assert_eq!(triggers.changed_triggers(&trigger_deactivated), names(&["dock"]));
+        assert_eq!(trigger_deactivated.changed_triggers(&triggers), names(&["dock"]));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_network_trigger() {
+        let config = load_test_config(
+            r#"
+            [trigger.dock]
+            type = "network"
+            interface = "enx00e04c680001"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let TriggerConfiguration::Network(dock) = &config.general.trigger["dock"] else {
+            panic!("unexpected trigger type");
+        };
+        assert_eq!(dock.interface, "enx00e04c680001");
+        assert_eq!(dock.poll_interval, Duration::from_secs(1));
+        let config = load_test_config(
+            r#"
+            [trigger.dock]
+            type = "network"
+            interface = "../eth0"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_drm_trigger() {
+        let config = load_test_config(
+            r#"
+            sysfs_root = "/tmp/fake-sys"
+
+            [trigger.monitor]
+            type = "drm"
+            connector = "DP-1"
+            poll_interval = "2s"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let TriggerConfiguration::Drm(monitor) = &config.general.trigger["monitor"] else {
+            panic!("unexpected trigger type");
+        };
+        assert_eq!(monitor.connector, "DP-1");
+        assert_eq!(monitor.poll_interval, Duration::from_secs(2));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_bluetooth_trigger() {
+        let config = load_test_config(
+            r#"
+            [trigger.keyboard]
+            type = "bluetooth"
+            address = "aa:bb:cc:dd:ee:ff"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let config = load_test_config(
+            r#"
+            [trigger.keyboard]
+            type = "bluetooth"
+            address = "aa:bb:cc:dd:ee"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_file_trigger() {
+        let config = load_test_config(
+            r#"
+            [trigger.script]
+            type = "file"
+            path = "/run/display-switch/pipe"
+            mode = "fifo"
+        "#,
         )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let TriggerConfiguration::File(script) = &config.general.trigger["script"] else {
+            panic!("unexpected trigger type");
+        };
+        assert_eq!(script.path, std::path::PathBuf::from("/run/display-switch/pipe"));
+        assert_eq!(script.mode, FileTriggerMode::Fifo);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use config::ConfigError;
-    use config::FileFormat::Ini;
 
     #[test]
-    fn test_log_file_name() {
-        let file_name = Configuration::log_file_name();
-        assert!(file_name.is_ok());
-        assert!(file_name.unwrap().ends_with("display-switch.log"))
+    fn test_invalid_triggers() {
+        let config = load_test_config(
+            r#"
+            usb_device = "046d:c52b"
+            triggers = "dock"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+        let config = load_test_config(
+            r#"
+            [trigger.dock]
+            type = "usb"
+            usb_serial = "ABC123"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+        assert!(load_test_config(
+            r#"
+            [trigger.dock]
+            type = "carrier-pigeon"
+        "#
+        )
+        .is_err());
     }
 
-    fn load_test_config(config_str: &str) -> Result<Configuration, ConfigError> {
-        config::Config::builder()
-            .add_source(config::File::from_str(config_str, Ini))
-            .build()?
-            .try_deserialize()
+    #[test]
+    fn test_duration_parsing() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration(" 5s ").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("5h").is_err());
+        assert!(parse_duration("s").is_err());
     }
 
     #[test]
-    fn test_usb_device_deserialization() {
+    fn test_debounce_deserialization() {
+        let config = load_test_config(r#"usb_device = "dead:beef""#).unwrap();
+        assert_eq!(config.general.debounce, Duration::ZERO);
+        assert_eq!(config.general.disconnect_delay, Duration::ZERO);
         let config = load_test_config(
             r#"
-            usb_device = "dead:BEEF"
-            on_usb_connect = "DisplayPort2"
+            usb_device = "dead:beef"
+            debounce = "250ms"
+            disconnect_delay = "5s"
         "#,
         )
         .unwrap();
-        assert_eq!(config.usb_device, "dead:beef")
+        assert_eq!(config.general.debounce, Duration::from_millis(250));
+        assert_eq!(config.general.disconnect_delay, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_usb_device_mode() {
+        assert!(UsbDeviceMode::Any.is_connected(1, 2));
+        assert!(!UsbDeviceMode::Any.is_connected(0, 2));
+        assert!(!UsbDeviceMode::All.is_connected(1, 2));
+        assert!(UsbDeviceMode::All.is_connected(2, 2));
     }
 
     #[test]
@@ -307,4 +1782,504 @@ mod tests {
             Some("bar".into())
         );
     }
+
+    #[test]
+    fn test_named_monitor_sections() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+            on_usb_connect = "0x10"
+
+            [monitor.center]
+            monitor_id = "dell"
+            on_usb_connect = 0x11
+
+            [monitor.left]
+            monitor_id = "len"
+            on_usb_connect = 0x12
+
+            [monitor10]
+            monitor_id = "dell u27"
+            on_usb_connect = 0x13
+
+            [monitor8]
+            monitor_id = "lenovo"
+            on_usb_connect = 0x14
+        "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        // Numbered sections come first, in numeric order
+        assert_eq!(config.monitor_section("LENOVO P27"), Some("monitor8".into()));
+        assert_eq!(config.monitor_section("DELL U2723"), Some("monitor10".into()));
+        assert_eq!(config.monitor_section("DELL P2419"), Some("center".into()));
+        assert_eq!(config.monitor_section("LEN P27u"), Some("left".into()));
+        assert_eq!(config.monitor_section("HP"), None);
+        assert_eq!(
+            config
+                .configuration_for_monitor("LEN P27u")
+                .on_usb_connect
+                .unwrap()
+                .value(),
+            0x12
+        );
+        assert_eq!(
+            config.configuration_for_monitor("HP").on_usb_connect.unwrap().value(),
+            0x10
+        );
+    }
+
+    #[test]
+    fn test_monitor_id_match() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+
+            [monitor1]
+            monitor_id = "dell u2723qe"
+            match = "exact"
+
+            [monitor2]
+            monitor_id = "DELL U27??DE"
+            match = "glob"
+
+            [monitor3]
+            monitor_id = "^len (p27|t24)"
+            match = "regex"
+
+            [monitor4]
+            monitor_id = "dell"
+        "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.monitor_section("DELL U2723QE"), Some("monitor1".into()));
+        assert_eq!(config.monitor_section("DELL U2723QE S/N 1"), Some("monitor4".into()));
+        assert_eq!(config.monitor_section("Dell U2721de"), Some("monitor2".into()));
+        assert_eq!(config.monitor_section("DELL U2721DE S/N 1"), Some("monitor4".into()));
+        assert_eq!(config.monitor_section("LEN P27u-10"), Some("monitor3".into()));
+        assert_eq!(config.monitor_section("LEN T27"), None);
+
+        let err = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+
+            [monitor1]
+            monitor_id = "len (p27"
+            match = "regex"
+        "#,
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains(r#"invalid monitor_id "len (p27""#),
+            "{:#}",
+            err
+        );
+        assert!(
+            load_test_config("usb_device = \"dead:beef\"\n[monitor1]\nmonitor_id = \"len\"\nmatch = \"fuzzy\"")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_monitor_sections() {
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+
+            [monitor.monitor1]
+            monitor_id = "dell"
+
+            [monitor1]
+            monitor_id = "len"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let result = load_test_config(
+            r#"
+            usb_device = "dead:BEEF"
+
+            [monitor01]
+            monitor_id = "dell"
+
+            [monitor1]
+            monitor_id = "len"
+        "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_native_types() {
+        let ini = load_test_config(
+            r#"
+            usb_device = "dead:beef, 1050:0407"
+            usb_hub_subtree = true
+            on_usb_connect = 0x11
+            debounce = 0
+
+            [monitor.left]
+            monitor_id = "len"
+            on_usb_disconnect = 18
+        "#,
+        )
+        .unwrap();
+        let toml = r#"
+            usb_device = ["dead:beef", "1050:0407"]
+            usb_hub_subtree = true
+            on_usb_connect = 0x11
+            debounce = 0
+
+            [monitor.left]
+            monitor_id = "len"
+            on_usb_disconnect = 18
+        "#;
+        let yaml = r#"
+usb_device: [dead:beef, 1050:0407]
+usb_hub_subtree: true
+on_usb_connect: 0x11
+debounce: 0
+monitor:
+  left:
+    monitor_id: len
+    on_usb_disconnect: 18
+"#;
+        let json = r#"{
+            "usb_device": ["dead:beef", "1050:0407"],
+            "usb_hub_subtree": true,
+            "on_usb_connect": 17,
+            "debounce": 0,
+            "monitor": {"left": {"monitor_id": "len", "on_usb_disconnect": 18}}
+        }"#;
+        for (config_str, format) in [(toml, Toml), (yaml, Yaml), (json, Json)] {
+            let config = load_config_str(config_str, format).unwrap();
+            assert_eq!(format!("{:?}", config), format!("{:?}", ini), "{:?}", format);
+        }
+        assert!(load_config_str("on_usb_connect = 70000", Toml).is_err());
+        assert!(load_config_str("debounce = 5", Toml).is_err());
+    }
+
+    #[test]
+    fn test_load_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        for (file_name, content) in [
+            (
+                "display-switch.ini",
+                "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi1\"\n",
+            ),
+            (
+                "display-switch.conf",
+                "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi1\"\n",
+            ),
+            (
+                "display-switch.toml",
+                "usb_device = \"dead:beef\"\non_usb_connect = \"Hdmi1\"\n",
+            ),
+            (
+                "display-switch.yml",
+                "usb_device: \"dead:beef\"\non_usb_connect: Hdmi1\n",
+            ),
+            (
+                "display-switch.json",
+                "{\"usb_device\": \"dead:beef\", \"on_usb_connect\": \"Hdmi1\"}",
+            ),
+        ] {
+            let path = dir.path().join(file_name);
+            std::fs::write(&path, content).unwrap();
+            let (config, _) = Configuration::load_with_settings(&path).unwrap();
+            assert_eq!(
+                config.usb.usb_device,
+                vec!["dead:beef".parse().unwrap()],
+                "{}",
+                file_name
+            );
+            assert_eq!(
+                config.default_input_sources.on_usb_connect.unwrap().value(),
+                0x11,
+                "{}",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff_settings() {
+        let settings = |config_str: &str| {
+            let mut settings = Settings::new();
+            let tree = config::Config::builder()
+                .add_source(config::File::from_str(config_str, Ini))
+                .build()
+                .unwrap()
+                .try_deserialize()
+                .unwrap();
+            flatten_settings("", &tree, &mut settings);
+            settings
+        };
+        let old = settings(
+            r#"
+            usb_device = "dead:beef"
+            on_usb_connect = "Hdmi1"
+            debounce = "100ms"
+
+            [monitor.left]
+            monitor_id = "len"
+        "#,
+        );
+        let new = settings(
+            r#"
+            usb_device = "dead:beef"
+            on_usb_connect = "DisplayPort1"
+
+            [monitor.left]
+            monitor_id = "len"
+
+            [monitor.right]
+            monitor_id = "dell"
+        "#,
+        );
+        assert_eq!(
+            diff_settings(&old, &new),
+            vec![
+                r#"debounce: "100ms" -> (unset)"#,
+                r#"monitor.right.monitor_id: (unset) -> "dell""#,
+                r#"on_usb_connect: "Hdmi1" -> "DisplayPort1""#,
+            ]
+        );
+        assert!(diff_settings(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_setting_names() {
+        let names = Configuration::setting_names();
+        for name in [
+            "trigger",
+            "debounce",
+            "monitor",
+            "active_profile",
+            "rule",
+            "usb_device",
+            "usb_hub_subtree",
+            "on_usb_connect",
+            "on_usb_disconnect_execute",
+        ] {
+            assert!(names.contains(&name), "{}", name);
+        }
+        // The flattened structs' fields are not settings themselves
+        assert!(!names.contains(&"general") && !names.contains(&"named"));
+        assert_eq!(TriggerConfiguration::setting_names("file"), Some(&["path", "mode"][..]));
+        assert_eq!(TriggerConfiguration::setting_names("serial"), None);
+        assert_eq!(monitor_number("monitor12"), Some(12));
+        assert_eq!(monitor_number("monitor"), None);
+        assert_eq!(monitor_number("monitor+1"), None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut config = load_test_config(
+            r#"
+            usb_device = "dead:beef"
+            on_usb_connect = "Hdmi1"
+            on_usb_disconnect = "Hdmi2"
+            on_usb_connect_execute = "echo connected"
+            active_profile = "work"
+
+            [monitor1]
+            monitor_id = "len"
+            on_usb_connect = "DisplayPort1"
+
+            [profile.work]
+            on_usb_connect = "DisplayPort2"
+
+            [profile.work.monitor.dell]
+            monitor_id = "dell"
+            on_usb_connect = "0x1b"
+
+            [profile.gaming]
+            on_usb_connect_execute = "echo gaming"
+
+            [profile.gaming.monitor1]
+            monitor_id = "len"
+            on_usb_disconnect = "0x1b"
+        "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        // The profile's settings take precedence, the top-level ones are used otherwise
+        assert_eq!(
+            config.configuration_for_monitor("HP").on_usb_connect.unwrap().value(),
+            0x10
+        );
+        assert_eq!(
+            config
+                .configuration_for_monitor("HP")
+                .on_usb_disconnect
+                .unwrap()
+                .value(),
+            0x12
+        );
+        assert_eq!(
+            config.configuration_for_monitor("DELL").on_usb_connect.unwrap().value(),
+            0x1b
+        );
+        assert_eq!(
+            config.configuration_for_monitor("LEN").on_usb_connect.unwrap().value(),
+            0x0f
+        );
+        assert_eq!(config.monitor_section("DELL"), Some("profile.work.dell".into()));
+        assert_eq!(
+            config.input_sources().on_usb_connect_execute,
+            Some("echo connected".into())
+        );
+
+        config.set_active_profile(Some("gaming".into())).unwrap();
+        assert_eq!(
+            config.configuration_for_monitor("HP").on_usb_connect.unwrap().value(),
+            0x11
+        );
+        assert_eq!(
+            config.configuration_for_monitor("LEN").on_usb_connect.unwrap().value(),
+            0x11
+        );
+        assert_eq!(
+            config
+                .configuration_for_monitor("LEN")
+                .on_usb_disconnect
+                .unwrap()
+                .value(),
+            0x1b
+        );
+        assert_eq!(
+            config.input_sources().on_usb_connect_execute,
+            Some("echo gaming".into())
+        );
+
+        config.set_active_profile(None).unwrap();
+        assert_eq!(
+            config.configuration_for_monitor("LEN").on_usb_connect.unwrap().value(),
+            0x0f
+        );
+        assert_eq!(config.monitor_section("DELL"), None);
+
+        assert!(config.set_active_profile(Some("home".into())).is_err());
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:beef"
+            active_profile = "home"
+
+            [profile.work]
+            on_usb_connect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = load_test_config(
+            r#"
+            on_usb_connect = "Hdmi1"
+            active_profile = "work"
+
+            [profile.work]
+            on_usb_disconnect = "Hdmi2"
+
+            [trigger.keyboard]
+            type = "usb"
+            usb_device = "046d:c52b"
+
+            [trigger.yubikey]
+            type = "usb"
+            usb_device = "1050:0407"
+
+            [rule.yubikey]
+            trigger = "yubikey"
+            on_usb_connect = "DisplayPort1"
+
+            [rule.yubikey.monitor.left]
+            monitor_id = "len"
+            on_usb_disconnect = "DisplayPort2"
+        "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.rule_name("yubikey"), Some("yubikey"));
+        assert_eq!(config.rule_name("keyboard"), None);
+
+        // A trigger without a rule uses the profile and top-level settings
+        let keyboard = config.for_trigger("keyboard");
+        assert_eq!(
+            keyboard
+                .configuration_for_monitor("LEN")
+                .on_usb_connect
+                .unwrap()
+                .value(),
+            0x11
+        );
+        assert_eq!(
+            keyboard
+                .configuration_for_monitor("LEN")
+                .on_usb_disconnect
+                .unwrap()
+                .value(),
+            0x12
+        );
+        assert_eq!(keyboard.monitor_section("LEN"), None);
+
+        // The rule's settings take precedence over the profile's and the top-level ones
+        let yubikey = config.for_trigger("yubikey");
+        assert_eq!(
+            yubikey.configuration_for_monitor("HP").on_usb_connect.unwrap().value(),
+            0x0f
+        );
+        assert_eq!(
+            yubikey
+                .configuration_for_monitor("HP")
+                .on_usb_disconnect
+                .unwrap()
+                .value(),
+            0x12
+        );
+        assert_eq!(
+            yubikey
+                .configuration_for_monitor("LEN")
+                .on_usb_disconnect
+                .unwrap()
+                .value(),
+            0x10
+        );
+        assert_eq!(yubikey.monitor_section("LEN"), Some("rule.yubikey.left".into()));
+
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:beef"
+
+            [rule.dock]
+            trigger = "dock"
+            on_usb_connect = "Hdmi1"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config = load_test_config(
+            r#"
+            usb_device = "dead:beef"
+
+            [rule.a]
+            trigger = "usb"
+            on_usb_connect = "Hdmi1"
+
+            [rule.b]
+            trigger = "usb"
+            on_usb_connect = "Hdmi2"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
 }
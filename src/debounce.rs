@@ -0,0 +1,171 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::time::{Duration, Instant};
+
+use crate::configuration::SwitchDirection;
+
+/// Coalesces bursts of device events into a single connect / disconnect transition. A transition is only
+/// reported once the monitored devices' state has not changed for the debounce window, and a transition that
+/// is reverted within the window (e.g. a device removed and re-added) is dropped altogether.
+/// Disconnects are additionally held back for a configurable delay, and cancelled if the devices return.
+pub struct Debouncer {
+    window: Duration,
+    disconnect_delay: Duration,
+    /// The last state that was reported
+    reported: SwitchDirection,
+    /// State that differs from the reported one, and when it's due to be reported
+    pending: Option<(SwitchDirection, Instant)>,
+}
+
+impl Debouncer {
+    pub fn new(window: Duration, disconnect_delay: Duration) -> Self {
+        Self {
+            window,
+            disconnect_delay,
+            reported: SwitchDirection::Disconnect,
+            pending: None,
+        }
+    }
+
+    /// Change the delays, e.g. after the configuration is reloaded: a pending transition keeps its deadline
+    pub fn set_delays(&mut self, window: Duration, disconnect_delay: Duration) {
+        self.window = window;
+        self.disconnect_delay = disconnect_delay;
+    }
+
+    /// Take the given state as already reported, dropping any pending transition
+    pub fn reset(&mut self, state: SwitchDirection) {
+        self.reported = state;
+        self.pending = None;
+    }
+
+    /// Record the state of the monitored devices after a device event
+    pub fn update(&mut self, state: SwitchDirection, now: Instant) {
+        if state == self.reported {
+            match self.pending.take() {
+                Some((SwitchDirection::Disconnect, _)) if !self.disconnect_delay.is_zero() => {
+                    info!("Monitored devices are back within the disconnect delay, cancelling the pending disconnect");
+                }
+                Some((pending, _)) => {
+                    debug!("Device state changed back before {} was reported, ignoring", pending);
+                }
+                None => {}
+            }
+        } else {
+            let deadline = match (state, self.pending) {
+                (SwitchDirection::Connect, _) => now + self.window,
+                // The disconnect delay counts from the first of several devices going away
+                (SwitchDirection::Disconnect, Some((SwitchDirection::Disconnect, deadline))) => {
+                    deadline.max(now + self.window)
+                }
+                (SwitchDirection::Disconnect, _) => now + self.window + self.disconnect_delay,
+            };
+            self.pending = Some((state, deadline));
+        }
+    }
+
+    /// When the pending transition, if any, is due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, deadline)| deadline)
+    }
+
+    /// Return the pending transition if it's due
+    pub fn poll(&mut self, now: Instant) -> Option<SwitchDirection> {
+        match self.pending {
+            Some((state, deadline)) if deadline <= now => {
+                self.pending = None;
+                self.reported = state;
+                Some(state)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn test_no_window_reports_immediately() {
+        let mut debouncer = Debouncer::new(Duration::ZERO, Duration::ZERO);
+        let now = Instant::now();
+        debouncer.update(SwitchDirection::Connect, now);
+        assert_eq!(debouncer.poll(now), Some(SwitchDirection::Connect));
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn test_burst_is_coalesced() {
+        let mut debouncer = Debouncer::new(WINDOW, Duration::ZERO);
+        let start = Instant::now();
+        debouncer.update(SwitchDirection::Connect, start);
+        debouncer.update(SwitchDirection::Connect, start + Duration::from_millis(100));
+        assert_eq!(debouncer.poll(start + Duration::from_millis(550)), None);
+        assert_eq!(
+            debouncer.poll(start + Duration::from_millis(600)),
+            Some(SwitchDirection::Connect)
+        );
+        assert_eq!(debouncer.poll(start + Duration::from_millis(700)), None);
+    }
+
+    #[test]
+    fn test_bounce_is_dropped() {
+        let mut debouncer = Debouncer::new(WINDOW, Duration::ZERO);
+        let start = Instant::now();
+        debouncer.update(SwitchDirection::Connect, start);
+        assert_eq!(debouncer.poll(start + WINDOW), Some(SwitchDirection::Connect));
+        // Removed and re-added within the window: nothing to report
+        debouncer.update(SwitchDirection::Disconnect, start + Duration::from_secs(1));
+        debouncer.update(SwitchDirection::Connect, start + Duration::from_millis(1100));
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(start + Duration::from_secs(2)), None);
+    }
+
+    #[test]
+    fn test_disconnect_is_held() {
+        let mut debouncer = Debouncer::new(Duration::ZERO, Duration::from_secs(5));
+        let start = Instant::now();
+        debouncer.update(SwitchDirection::Connect, start);
+        assert_eq!(debouncer.poll(start), Some(SwitchDirection::Connect));
+        debouncer.update(SwitchDirection::Disconnect, start);
+        assert_eq!(debouncer.poll(start + Duration::from_secs(4)), None);
+        assert_eq!(
+            debouncer.poll(start + Duration::from_secs(5)),
+            Some(SwitchDirection::Disconnect)
+        );
+    }
+
+    #[test]
+    fn test_repeated_disconnect_keeps_deadline() {
+        let mut debouncer = Debouncer::new(Duration::ZERO, Duration::from_secs(5));
+        let start = Instant::now();
+        debouncer.update(SwitchDirection::Connect, start);
+        assert_eq!(debouncer.poll(start), Some(SwitchDirection::Connect));
+        // Two of the monitored devices go away one after the other
+        debouncer.update(SwitchDirection::Disconnect, start);
+        debouncer.update(SwitchDirection::Disconnect, start + Duration::from_secs(3));
+        assert_eq!(debouncer.deadline(), Some(start + Duration::from_secs(5)));
+        assert_eq!(
+            debouncer.poll(start + Duration::from_secs(5)),
+            Some(SwitchDirection::Disconnect)
+        );
+    }
+
+    #[test]
+    fn test_held_disconnect_is_cancelled() {
+        let mut debouncer = Debouncer::new(Duration::ZERO, Duration::from_secs(5));
+        let start = Instant::now();
+        debouncer.update(SwitchDirection::Connect, start);
+        assert_eq!(debouncer.poll(start), Some(SwitchDirection::Connect));
+        debouncer.update(SwitchDirection::Disconnect, start);
+        debouncer.update(SwitchDirection::Connect, start + Duration::from_secs(3));
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(start + Duration::from_secs(10)), None);
+    }
+}
@@ -88,6 +88,17 @@ fn displays() -> Vec<Display> {
     Display::enumerate()
 }
 
+/// Names of the connected DDC-compatible displays, as matched against the monitor sections' `monitor_id`
+pub fn display_names() -> Vec<String> {
+    let displays = displays();
+    let unique_names = are_display_names_unique(&displays);
+    displays
+        .iter()
+        .enumerate()
+        .map(|(index, display)| display_name(display, if unique_names { None } else { Some(index + 1) }))
+        .collect()
+}
+
 pub fn log_current_source() {
     let displays = displays();
     if displays.is_empty() {
@@ -118,6 +129,13 @@ pub fn switch(config: &Configuration, switch_direction: SwitchDirection) {
     let unique_names = are_display_names_unique(&displays);
     for (index, mut display) in displays.into_iter().enumerate() {
         let display_name = display_name(&display, if unique_names { None } else { Some(index + 1) });
+        match config.monitor_section(&display_name) {
+            Some(section) => info!(
+                "Display {} is configured by monitor section {:?}",
+                display_name, section
+            ),
+            None => debug!("Display {} matches no monitor section", display_name),
+        }
         let input_sources = config.configuration_for_monitor(&display_name);
         debug!("Input sources found for display {}: {:?}", display_name, input_sources);
         if let Some(input) = input_sources.source(switch_direction) {
@@ -132,7 +150,7 @@ pub fn switch(config: &Configuration, switch_direction: SwitchDirection) {
             run_command(execute_command)
         }
     }
-    if let Some(execute_command) = config.default_input_sources.execute_command(switch_direction) {
+    if let Some(execute_command) = config.input_sources().execute_command(switch_direction) {
         run_command(execute_command)
     }
 }
@@ -79,9 +79,9 @@ impl InputSource {
 }
 
 fn parse_int(s: &str) -> Result<u16, std::num::ParseIntError> {
-    if s.starts_with("0x") {
+    if let Some(hex) = s.strip_prefix("0x") {
         // Parse as hexadecimal
-        u16::from_str_radix(&s[2..], 16)
+        u16::from_str_radix(hex, 16)
     } else {
         // Parse as decimal
         s.parse::<u16>()
@@ -93,7 +93,22 @@ impl<'de> Deserialize<'de> for InputSource {
     where
         D: Deserializer<'de>,
     {
-        let str = String::deserialize(deserializer)?.trim().to_lowercase();
+        /// Integers come from formats that have them, such as TOML, YAML or JSON
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum IntOrString {
+            Int(i64),
+            String(String),
+        }
+
+        let str = match IntOrString::deserialize(deserializer)? {
+            IntOrString::Int(value) => {
+                return u16::try_from(value)
+                    .map(|value| Self::Raw(value).normalize())
+                    .map_err(|_| D::Error::custom(format!("Invalid input source: {}", value)));
+            }
+            IntOrString::String(s) => s.trim().to_lowercase(),
+        };
         if let Ok(val) = parse_int(&str) {
             Ok(Self::Raw(val).normalize())
         } else {
@@ -8,22 +8,29 @@
 extern crate log;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[cfg(target_os = "windows")]
 use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
 
 mod app;
+mod commands;
+mod config_watcher;
 mod configuration;
+mod debounce;
 mod display_control;
 mod input_source;
 mod logging;
 mod platform;
+mod trigger;
 mod usb;
 
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Print debug information
     #[arg(short, long, default_value_t = false)]
     debug: bool,
@@ -31,6 +38,36 @@ struct Args {
     /// Path to an alternative configuration file
     #[arg(short = 'c', long = "config")]
     config_file_path: Option<std::path::PathBuf>,
+
+    /// How USB devices are detected, overrides the `usb_detector` configuration setting
+    #[arg(long, value_enum)]
+    usb_detector: Option<configuration::UsbDetector>,
+
+    /// Profile to use, overrides the `active_profile` configuration setting
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the connected USB devices, with the IDs to use in the configuration
+    ListUsb {
+        /// Print the devices as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Find the USB device toggled by the USB switch, and optionally save it in the configuration
+    IdentifyUsb,
+    /// Check the configuration file for errors, and exit with a non-zero status if there are any
+    CheckConfig {
+        /// The configuration file to check, the one in use by default
+        path: Option<std::path::PathBuf>,
+    },
+    /// Make a profile the active one in the configuration file, a running display-switch switches to it
+    SetProfile {
+        /// Name of the profile, none to use the top-level settings only
+        name: Option<String>,
+    },
 }
 
 /// On Windows, re-attach the console, if parent process has the console. This allows
@@ -46,7 +83,15 @@ fn main() -> Result<()> {
     attach_console();
     let args = Args::parse();
 
-    let app = app::App::new(args)?;
-    app.run()?;
-    Ok(())
+    match args.command {
+        Some(Command::ListUsb { json }) => commands::list_usb(json),
+        Some(Command::IdentifyUsb) => commands::identify_usb(args.config_file_path.as_deref()),
+        Some(Command::CheckConfig { path }) => commands::check_config(path.or(args.config_file_path).as_deref()),
+        Some(Command::SetProfile { name }) => commands::set_profile(args.config_file_path.as_deref(), name.as_deref()),
+        None => {
+            let app = app::App::new(args)?;
+            app.run()?;
+            Ok(())
+        }
+    }
 }
@@ -3,6 +3,11 @@
 // This code is licensed under MIT license (see LICENSE.txt for details)
 //
 
+use anyhow::Result;
+
+use crate::configuration::{Configuration, UsbDetector};
+use crate::usb::UsbCallback;
+
 #[cfg(target_os = "macos")]
 mod pnp_detect_libusb;
 #[cfg(target_os = "macos")]
@@ -13,6 +18,13 @@ mod pnp_detect_libusb;
 #[cfg(target_os = "linux")]
 pub use pnp_detect_libusb::PnPDetectLibusb as PnPDetect;
 
+#[cfg(target_os = "linux")]
+mod pnp_detect_sysfs;
+#[cfg(target_os = "linux")]
+mod pnp_detect_uevent;
+#[cfg(target_os = "linux")]
+mod sysfs_usb;
+
 #[cfg(target_os = "windows")]
 mod pnp_detect_windows;
 #[cfg(target_os = "windows")]
@@ -20,3 +32,25 @@ pub use pnp_detect_windows::PnPDetectWindows as PnPDetect;
 
 mod wake_displays;
 pub use wake_displays::wake_displays;
+
+/// Run the configured USB device detector: reports device events to the callback until it's stopped, and only returns
+/// earlier on failure
+pub fn detect_usb_devices(config: &Configuration, callback: Box<dyn UsbCallback>) -> Result<()> {
+    match config.general.usb_detector {
+        UsbDetector::Default => PnPDetect::new(callback).detect(),
+        #[cfg(target_os = "linux")]
+        UsbDetector::Uevent => pnp_detect_uevent::PnPDetectUevent::new(callback, &config.general.sysfs_root).detect(),
+        #[cfg(target_os = "linux")]
+        UsbDetector::Sysfs => pnp_detect_sysfs::PnPDetectSysfs::new(
+            callback,
+            &config.general.sysfs_root,
+            config.general.usb_poll_interval,
+        )
+        .detect(),
+        #[cfg(not(target_os = "linux"))]
+        detector => Err(anyhow::anyhow!(
+            "{:?} USB detector is only supported on Linux",
+            detector
+        )),
+    }
+}
@@ -3,52 +3,279 @@
 // This code is licensed under MIT license (see LICENSE.txt for details)
 //
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, Result};
 use rusb::{Context, Device, HotplugBuilder, Registration, UsbContext};
 
+use crate::trigger::STOP_CHECK_INTERVAL;
+use crate::usb::{UsbCallback, UsbDevice};
+
+/// Consecutive event handling failures after which the libusb context and the hotplug registration are re-created
+const FAILURES_BEFORE_RECREATE: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Devices that have arrived, by bus number and address: a device that has left cannot be opened
+/// anymore to read its string descriptors, so the identity captured on arrival is reported instead.
+type KnownDevices = Arc<Mutex<HashMap<(u8, u8), UsbDevice>>>;
+
 /// Detection of plugged in / removed USB devices: uses "libusb" and should work on Linux
 /// and MacOS, but not on Windows: libusb does not support hotplug on Windows.
 pub struct PnPDetectLibusb {
-    callback: Box<dyn crate::usb::UsbCallback + Send>,
+    callback: Arc<dyn UsbCallback>,
+    devices: KnownDevices,
 }
 
-impl<T: UsbContext> rusb::Hotplug<T> for PnPDetectLibusb {
+/// Receives the hotplug events of one libusb context
+struct HotplugHandler {
+    callback: Arc<dyn UsbCallback>,
+    devices: KnownDevices,
+}
+
+impl<T: UsbContext> rusb::Hotplug<T> for HotplugHandler {
     fn device_arrived(&mut self, device: Device<T>) {
-        if let Some(str) = crate::usb::device_id(&device) {
-            self.callback.device_added(&str)
+        let key = (device.bus_number(), device.address());
+        let mut devices = self.devices.lock().unwrap();
+        // Already reported when the devices were re-synchronized after re-creating the context
+        if devices.contains_key(&key) {
+            return;
+        }
+        if let Some(usb_device) = UsbDevice::from_device(&device) {
+            self.callback.device_added(&usb_device);
+            devices.insert(key, usb_device);
         }
     }
 
     fn device_left(&mut self, device: Device<T>) {
-        if let Some(str) = crate::usb::device_id(&device) {
-            self.callback.device_removed(&str)
+        let usb_device = self
+            .devices
+            .lock()
+            .unwrap()
+            .remove(&(device.bus_number(), device.address()))
+            .or_else(|| UsbDevice::from_device(&device));
+        if let Some(usb_device) = usb_device {
+            self.callback.device_removed(&usb_device)
         }
     }
 }
 
-impl PnPDetectLibusb {
-    pub fn new(callback: Box<dyn crate::usb::UsbCallback + Send>) -> Box<Self> {
-        Box::new(PnPDetectLibusb { callback })
+/// How an error from the libusb event handling is dealt with
+#[derive(Debug, PartialEq, Eq)]
+enum ErrorClass {
+    /// Not a failure, e.g. a signal has interrupted the wait for events
+    Spurious,
+    /// Something went wrong, and might go away
+    Transient,
+    /// Retrying won't help
+    Fatal,
+}
+
+fn classify(err: &rusb::Error) -> ErrorClass {
+    match err {
+        rusb::Error::Interrupted | rusb::Error::Timeout => ErrorClass::Spurious,
+        rusb::Error::NotSupported | rusb::Error::Access => ErrorClass::Fatal,
+        _ => ErrorClass::Transient,
     }
+}
 
-    pub fn detect(self) -> Result<()> {
-        if rusb::has_hotplug() {
-            let context = Context::new()?;
+/// What to do after an event handling error
+#[derive(Debug, PartialEq, Eq)]
+enum Recovery {
+    RetryNow,
+    RetryAfter(Duration),
+    /// Wait, then re-create the libusb context and the hotplug registration
+    Recreate(Duration),
+}
 
-            let _reg: std::option::Option<Registration<rusb::Context>> = Some(
-                HotplugBuilder::new()
-                    .enumerate(true)
-                    .register(&context, Box::new(self))?,
-            );
+/// Tracks consecutive event handling failures: a streak of failures is logged once when it starts and once when
+/// it ends, the attempts in between only at debug level
+#[derive(Default)]
+struct FailureTracker {
+    failures: u32,
+}
 
-            loop {
-                if let Err(err) = context.handle_events(None) {
-                    error!("Error during USB errors handling: {:?}", err)
-                };
+impl FailureTracker {
+    fn failed(&mut self, err: rusb::Error) -> Result<Recovery> {
+        match classify(&err) {
+            ErrorClass::Spurious => {
+                debug!("USB event handling was interrupted: {:?}", err);
+                return Ok(Recovery::RetryNow);
             }
+            ErrorClass::Fatal => return Err(anyhow!("USB event handling has failed: {:?}", err)),
+            ErrorClass::Transient => {}
+        }
+        self.failures += 1;
+        if self.failures == 1 {
+            warn!("USB event handling has failed, retrying with a backoff: {:?}", err);
         } else {
+            debug!("USB event handling has failed {} times: {:?}", self.failures, err);
+        }
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.failures - 1))
+            .min(MAX_BACKOFF);
+        if self.failures % FAILURES_BEFORE_RECREATE == 0 {
+            debug!(
+                "USB event handling has failed {} times in a row, re-creating the libusb context",
+                self.failures
+            );
+            Ok(Recovery::Recreate(delay))
+        } else {
+            Ok(Recovery::RetryAfter(delay))
+        }
+    }
+
+    fn succeeded(&mut self) {
+        if self.failures > 0 {
+            info!("USB event handling has recovered after {} failures", self.failures);
+            self.failures = 0;
+        }
+    }
+}
+
+impl PnPDetectLibusb {
+    pub fn new(callback: Box<dyn UsbCallback>) -> Box<Self> {
+        Box::new(PnPDetectLibusb {
+            callback: Arc::from(callback),
+            devices: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn register(&self, enumerate: bool) -> rusb::Result<(Context, Registration<Context>)> {
+        let context = Context::new()?;
+        let handler = HotplugHandler {
+            callback: self.callback.clone(),
+            devices: self.devices.clone(),
+        };
+        let registration = HotplugBuilder::new()
+            .enumerate(enumerate)
+            .register(&context, Box::new(handler))?;
+        Ok((context, registration))
+    }
+
+    /// Report the devices that have arrived or left while no context was handling the events
+    fn resync(&self, context: &Context) -> rusb::Result<()> {
+        let present = context
+            .devices()?
+            .iter()
+            .map(|device| ((device.bus_number(), device.address()), device))
+            .collect::<HashMap<(u8, u8), Device<Context>>>();
+        let mut devices = self.devices.lock().unwrap();
+        devices.retain(|key, usb_device| {
+            let is_present = present.contains_key(key);
+            if !is_present {
+                self.callback.device_removed(usb_device);
+            }
+            is_present
+        });
+        for (key, device) in present {
+            if devices.contains_key(&key) {
+                continue;
+            }
+            if let Some(usb_device) = UsbDevice::from_device(&device) {
+                self.callback.device_added(&usb_device);
+                devices.insert(key, usb_device);
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait before retrying, checking every `STOP_CHECK_INTERVAL` whether the detection should stop: returns false if
+    /// it should
+    fn sleep_unless_stopped(&self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+        while !self.callback.stopped() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+            thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+        }
+        false
+    }
+
+    pub fn detect(self) -> Result<()> {
+        if !rusb::has_hotplug() {
             // This should never happen: hotplug is supported on Linux and MacOS both.
-            Err(anyhow!("libusb hotplug api unsupported"))
+            return Err(anyhow!("libusb hotplug api unsupported"));
         }
+        // With "enumerate", the devices already present are reported before the registration returns
+        let (mut context, mut _registration) = self.register(true)?;
+        self.callback.devices_enumerated();
+
+        let mut failures = FailureTracker::default();
+        while !self.callback.stopped() {
+            let err = match context.handle_events(Some(STOP_CHECK_INTERVAL)) {
+                Ok(()) => {
+                    failures.succeeded();
+                    continue;
+                }
+                Err(err) => err,
+            };
+            match failures.failed(err)? {
+                Recovery::RetryNow => {}
+                Recovery::RetryAfter(delay) => {
+                    if !self.sleep_unless_stopped(delay) {
+                        break;
+                    }
+                }
+                Recovery::Recreate(delay) => {
+                    if !self.sleep_unless_stopped(delay) {
+                        break;
+                    }
+                    let recreated = self.register(false).and_then(|(new_context, registration)| {
+                        self.resync(&new_context)?;
+                        Ok((new_context, registration))
+                    });
+                    match recreated {
+                        Ok((new_context, registration)) => {
+                            context = new_context;
+                            _registration = registration;
+                        }
+                        // The old context is kept, and re-creating it is attempted again after more failures
+                        Err(err) => debug!("Failed to re-create the libusb context: {:?}", err),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_classification() {
+        assert_eq!(classify(&rusb::Error::Interrupted), ErrorClass::Spurious);
+        assert_eq!(classify(&rusb::Error::Io), ErrorClass::Transient);
+        assert_eq!(classify(&rusb::Error::NoMem), ErrorClass::Transient);
+        assert_eq!(classify(&rusb::Error::NotSupported), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn test_backoff_and_recreate() {
+        let mut failures = FailureTracker::default();
+        assert_eq!(failures.failed(rusb::Error::Interrupted).unwrap(), Recovery::RetryNow);
+        let recoveries = (0..12)
+            .map(|_| failures.failed(rusb::Error::Io).unwrap())
+            .collect::<Vec<Recovery>>();
+        assert_eq!(recoveries[0], Recovery::RetryAfter(Duration::from_millis(100)));
+        assert_eq!(recoveries[1], Recovery::RetryAfter(Duration::from_millis(200)));
+        assert_eq!(recoveries[3], Recovery::RetryAfter(Duration::from_millis(800)));
+        assert_eq!(recoveries[4], Recovery::Recreate(Duration::from_millis(1600)));
+        assert_eq!(recoveries[9], Recovery::Recreate(MAX_BACKOFF));
+        assert_eq!(recoveries[11], Recovery::RetryAfter(MAX_BACKOFF));
+
+        failures.succeeded();
+        assert_eq!(
+            failures.failed(rusb::Error::Io).unwrap(),
+            Recovery::RetryAfter(INITIAL_BACKOFF)
+        );
+        assert!(failures.failed(rusb::Error::Access).is_err());
     }
 }
@@ -0,0 +1,97 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::platform::sysfs_usb;
+use crate::usb::UsbDevice;
+
+/// Detection of plugged in / removed USB devices on Linux by polling "/sys/bus/usb/devices": a fallback for
+/// restricted environments where neither libusb hotplug nor kernel uevents are available.
+pub struct PnPDetectSysfs {
+    callback: Box<dyn crate::usb::UsbCallback>,
+    sysfs_root: PathBuf,
+    interval: Duration,
+    /// Devices seen during the last poll, by kernel name
+    current_devices: HashMap<String, UsbDevice>,
+}
+
+impl PnPDetectSysfs {
+    pub fn new(callback: Box<dyn crate::usb::UsbCallback>, sysfs_root: &Path, interval: Duration) -> Box<Self> {
+        Box::new(Self {
+            callback,
+            sysfs_root: sysfs_root.to_path_buf(),
+            interval,
+            current_devices: HashMap::new(),
+        })
+    }
+
+    pub fn detect(mut self) -> Result<()> {
+        // Fail right away if sysfs is not readable at all, rather than logging errors forever
+        sysfs_usb::read_devices(&self.sysfs_root)?;
+        self.poll();
+        self.callback.devices_enumerated();
+        while !self.callback.stopped() {
+            thread::sleep(self.interval);
+            self.poll();
+        }
+        Ok(())
+    }
+
+    /// Compare the current list of devices with the one from the previous poll, and report the differences
+    fn poll(&mut self) {
+        let new_devices = match sysfs_usb::read_devices(&self.sysfs_root) {
+            Ok(devices) => devices,
+            Err(err) => {
+                error!("Cannot get a list of USB devices: {:?}", err);
+                return;
+            }
+        };
+        // A device replaced by another one between two polls is reported as removed and added
+        for (name, device) in self.current_devices.iter() {
+            if new_devices.get(name) != Some(device) {
+                self.callback.device_removed(device);
+            }
+        }
+        for (name, device) in new_devices.iter() {
+            if self.current_devices.get(name) != Some(device) {
+                self.callback.device_added(device);
+            }
+        }
+        self.current_devices = new_devices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::sysfs_usb::tests::{FakeSysfs, RecordingCallback};
+
+    #[test]
+    fn test_poll_reports_differences() {
+        let sysfs = FakeSysfs::new("poll");
+        sysfs.add_device("1-1", &[("idVendor", "1050"), ("idProduct", "0407")]);
+        let events = RecordingCallback::default();
+        let mut detector = PnPDetectSysfs::new(Box::new(events.clone()), &sysfs.root, Duration::ZERO);
+
+        detector.poll();
+        assert_eq!(events.take(), vec!["+1050:0407"]);
+        detector.poll();
+        assert!(events.take().is_empty());
+
+        sysfs.add_device("1-3.2", &[("idVendor", "046d"), ("idProduct", "c52b")]);
+        detector.poll();
+        assert_eq!(events.take(), vec!["+046d:c52b"]);
+
+        sysfs.remove_device("1-1");
+        detector.poll();
+        assert_eq!(events.take(), vec!["-1050:0407"]);
+    }
+}
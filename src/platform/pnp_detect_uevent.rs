@@ -0,0 +1,263 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::platform::sysfs_usb;
+use crate::trigger::STOP_CHECK_INTERVAL;
+use crate::usb::UsbDevice;
+
+/// Kernel uevents are multicast to this netlink group (udev re-broadcasts processed events to group 2)
+const KERNEL_UEVENT_GROUP: u32 = 1;
+const UEVENT_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Detection of plugged in / removed USB devices on Linux: listens to kernel uevents on a netlink socket,
+/// and reads the devices' attributes from sysfs. Does not need libusb hotplug support.
+pub struct PnPDetectUevent {
+    callback: Box<dyn crate::usb::UsbCallback>,
+    sysfs_root: PathBuf,
+    /// Devices that have arrived, by kernel name: once a device is removed, its sysfs attributes are gone
+    devices: HashMap<String, UsbDevice>,
+}
+
+/// A parsed kernel uevent message
+#[derive(Debug)]
+struct Uevent {
+    action: String,
+    devpath: String,
+    properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Kernel messages are "ACTION@DEVPATH", followed by "KEY=VALUE" properties, all NUL-separated
+    fn parse(message: &[u8]) -> Option<Self> {
+        let mut fields = message
+            .split(|b| *b == 0)
+            .filter(|field| !field.is_empty())
+            .map(String::from_utf8_lossy);
+        let (action, devpath) = fields
+            .next()?
+            .split_once('@')
+            .map(|(a, d)| (a.to_owned(), d.to_owned()))?;
+        let properties = fields
+            .filter_map(|field| field.split_once('=').map(|(k, v)| (k.to_owned(), v.to_owned())))
+            .collect();
+        Some(Self {
+            action,
+            devpath,
+            properties,
+        })
+    }
+
+    fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(String::as_str)
+    }
+
+    /// Whether this event is about a USB device (as opposed to a USB interface, or another subsystem)
+    fn is_usb_device(&self) -> bool {
+        self.property("SUBSYSTEM") == Some("usb") && self.property("DEVTYPE") == Some("usb_device")
+    }
+
+    /// Kernel name of the device, e.g. "1-3.2"
+    fn name(&self) -> &str {
+        self.devpath.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Same as the kernel name, except for root hubs ("usbN"), which are identified by the bus number
+    fn port_path(&self) -> Option<String> {
+        match self.name().strip_prefix("usb") {
+            Some(_) => self.property("BUSNUM")?.parse::<u8>().ok().map(|bus| bus.to_string()),
+            None => Some(self.name().to_owned()),
+        }
+    }
+
+    /// Identity of the device from the event itself: used when the device is not in sysfs (anymore)
+    fn device(&self) -> Option<UsbDevice> {
+        // "PRODUCT" is "vendor/product/bcdDevice" in hex, without leading zeros
+        let mut product = self.property("PRODUCT")?.split('/');
+        let vendor_id = u16::from_str_radix(product.next()?, 16).ok()?;
+        let product_id = u16::from_str_radix(product.next()?, 16).ok()?;
+        Some(UsbDevice {
+            id: format!("{:04x}:{:04x}", vendor_id, product_id),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            port_path: self.port_path(),
+            devpath: Some(self.devpath.clone()),
+        })
+    }
+}
+
+/// Netlink socket subscribed to kernel uevents
+fn open_uevent_socket() -> io::Result<File> {
+    // SAFETY: `socket` returns a new descriptor that nothing else owns, so `OwnedFd` can take it over, and the
+    // address and the timeout are initialized structs of the sizes passed along with them
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+        let mut address: libc::sockaddr_nl = std::mem::zeroed();
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = KERNEL_UEVENT_GROUP;
+        let result = libc::bind(
+            fd.as_raw_fd(),
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Reads time out, so that the detection can check whether it should stop
+        let timeout = libc::timeval {
+            tv_sec: STOP_CHECK_INTERVAL.as_secs() as libc::time_t,
+            tv_usec: STOP_CHECK_INTERVAL.subsec_micros() as libc::suseconds_t,
+        };
+        let result = libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        );
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from(fd))
+    }
+}
+
+impl PnPDetectUevent {
+    pub fn new(callback: Box<dyn crate::usb::UsbCallback>, sysfs_root: &Path) -> Box<Self> {
+        Box::new(Self {
+            callback,
+            sysfs_root: sysfs_root.to_path_buf(),
+            devices: HashMap::new(),
+        })
+    }
+
+    pub fn detect(mut self) -> Result<()> {
+        // Subscribe before enumerating, so that no device plugged in meanwhile is missed
+        let mut socket = open_uevent_socket().context("failed to open a kernel uevent socket")?;
+        self.enumerate()?;
+
+        let mut buffer = vec![0u8; UEVENT_BUFFER_SIZE];
+        while !self.callback.stopped() {
+            match socket.read(&mut buffer) {
+                Ok(size) => {
+                    if let Some(uevent) = Uevent::parse(&buffer[..size]) {
+                        self.handle_uevent(&uevent);
+                    }
+                }
+                // The kernel has dropped some events because they were not read fast enough
+                Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!("Some USB device events were lost: {:?}", err);
+                }
+                Err(err) if matches!(err.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock) => {}
+                Err(err) => return Err(err).context("failed to read kernel uevents"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Report the devices already present
+    fn enumerate(&mut self) -> Result<()> {
+        for (name, device) in sysfs_usb::read_devices(&self.sysfs_root)? {
+            self.callback.device_added(&device);
+            self.devices.insert(name, device);
+        }
+        self.callback.devices_enumerated();
+        Ok(())
+    }
+
+    fn handle_uevent(&mut self, uevent: &Uevent) {
+        if !uevent.is_usb_device() {
+            return;
+        }
+        let name = uevent.name().to_owned();
+        match uevent.action.as_str() {
+            // Plugged in while the devices were enumerated, and reported already
+            "add" if self.devices.contains_key(&name) => {
+                debug!("Device {} was already reported, ignoring its \"add\" event", name);
+            }
+            "add" => {
+                let device = sysfs_usb::read_device(&self.sysfs_root, &name).or_else(|| uevent.device());
+                if let Some(device) = device {
+                    self.callback.device_added(&device);
+                    self.devices.insert(name, device);
+                }
+            }
+            "remove" => {
+                let device = self.devices.remove(&name).or_else(|| uevent.device());
+                if let Some(device) = device {
+                    self.callback.device_removed(&device);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::sysfs_usb::tests::{FakeSysfs, RecordingCallback};
+
+    #[test]
+    fn test_uevent_parsing() {
+        let message = b"remove@/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2\0ACTION=remove\0\
+            DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0\
+            PRODUCT=46d/c52b/1201\0BUSNUM=001\0DEVNUM=005\0SEQNUM=4242\0";
+        let uevent = Uevent::parse(message).unwrap();
+        assert_eq!(uevent.action, "remove");
+        assert!(uevent.is_usb_device());
+        assert_eq!(uevent.name(), "1-3.2");
+        let device = uevent.device().unwrap();
+        assert_eq!(device.id, "046d:c52b");
+        assert_eq!(device.port_path.as_deref(), Some("1-3.2"));
+        assert_eq!(
+            device.devpath.as_deref(),
+            Some("/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2")
+        );
+    }
+
+    #[test]
+    fn test_uevent_for_interface_is_ignored() {
+        let message = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2/1-3.2:1.0\0ACTION=add\0\
+            SUBSYSTEM=usb\0DEVTYPE=usb_interface\0PRODUCT=46d/c52b/1201\0";
+        let uevent = Uevent::parse(message).unwrap();
+        assert!(!uevent.is_usb_device());
+        assert!(Uevent::parse(b"").is_none());
+    }
+
+    #[test]
+    fn test_add_during_enumeration_is_reported_once() {
+        let sysfs = FakeSysfs::new("uevent-enumerate");
+        sysfs.add_device("1-3.2", &[("idVendor", "046d"), ("idProduct", "c52b")]);
+        let events = RecordingCallback::default();
+        let mut detector = PnPDetectUevent::new(Box::new(events.clone()), &sysfs.root);
+        detector.enumerate().unwrap();
+
+        // The device was plugged in after the socket was opened, and before sysfs was read
+        let add = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2\0ACTION=add\0\
+            SUBSYSTEM=usb\0DEVTYPE=usb_device\0PRODUCT=46d/c52b/1201\0";
+        detector.handle_uevent(&Uevent::parse(add).unwrap());
+        let remove = b"remove@/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2\0ACTION=remove\0\
+            SUBSYSTEM=usb\0DEVTYPE=usb_device\0PRODUCT=46d/c52b/1201\0";
+        detector.handle_uevent(&Uevent::parse(remove).unwrap());
+        assert_eq!(events.take(), vec!["+046d:c52b", "-046d:c52b"]);
+    }
+}
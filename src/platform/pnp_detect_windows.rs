@@ -8,23 +8,32 @@ use std::ffi::OsStr;
 use std::iter::once;
 use std::os::windows::ffi::OsStrExt;
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
 use winapi::shared::ntdef::LPCWSTR;
 use winapi::shared::windef::{HBRUSH, HCURSOR, HICON, HWND};
+use winapi::shared::winerror::ERROR_CLASS_ALREADY_EXISTS;
+use winapi::um::errhandlingapi::GetLastError;
 use winapi::um::libloaderapi::GetModuleHandleW;
 use winapi::um::winuser::{
-    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, GetWindowLongPtrW, PostQuitMessage, RegisterClassW,
-    SetWindowLongPtrW, TranslateMessage, GWLP_USERDATA, MSG, WM_CREATE, WM_DESTROY, WM_DEVICECHANGE, WNDCLASSW,
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW, GetWindowLongPtrW, PostQuitMessage,
+    RegisterClassW, SetTimer, SetWindowLongPtrW, TranslateMessage, GWLP_USERDATA, MSG, WM_CREATE, WM_DESTROY,
+    WM_DEVICECHANGE, WNDCLASSW,
 };
 
+use crate::trigger::STOP_CHECK_INTERVAL;
+use crate::usb::UsbDevice;
+
+/// The timer that wakes up the event loop, to check whether the detection should stop
+const STOP_CHECK_TIMER_ID: usize = 1;
+
 /// Detection of plugged in / removed USB devices on Windows: listens for WM_DEVICECHANGE messages.
 /// This code should be removed once libusb supports hotplug notifications on Windows:
 /// https://github.com/libusb/libusb/issues/86
 pub struct PnPDetectWindows {
     hwnd: HWND,
     callback: Box<dyn crate::usb::UsbCallback>,
-    current_devices: HashSet<String>,
+    current_devices: HashSet<UsbDevice>,
 }
 
 impl PnPDetectWindows {
@@ -58,18 +67,25 @@ impl PnPDetectWindows {
     }
 
     /// Get a list of currently connected USB devices
-    fn read_device_list() -> Result<HashSet<String>> {
-        Ok(rusb::devices()?
-            .iter()
-            .map(|device| crate::usb::device_id(&device).ok_or(anyhow!("Cannot get device Ids")))
-            .collect::<std::result::Result<_, _>>()?)
+    fn read_device_list() -> Result<HashSet<UsbDevice>> {
+        Ok(crate::usb::connected_devices()?.into_iter().collect())
     }
 
     /// Detect USB events: just run a Windows event loop
     pub fn detect(&self) -> Result<()> {
+        for device in self.current_devices.iter() {
+            self.callback.device_added(device);
+        }
+        self.callback.devices_enumerated();
         unsafe {
+            SetTimer(
+                self.hwnd,
+                STOP_CHECK_TIMER_ID,
+                STOP_CHECK_INTERVAL.as_millis() as UINT,
+                None,
+            );
             let mut msg: MSG = std::mem::MaybeUninit::zeroed().assume_init();
-            loop {
+            while !self.callback.stopped() {
                 let val = GetMessageW(&mut msg, self.hwnd, 0, 0);
                 if val == 0 {
                     break;
@@ -78,6 +94,7 @@ impl PnPDetectWindows {
                     DispatchMessageW(&msg);
                 }
             }
+            DestroyWindow(self.hwnd);
         }
         Ok(())
     }
@@ -124,8 +141,12 @@ impl PnPDetectWindows {
             lpszClassName: winapi_class_name.as_ptr(),
         };
 
+        // Already registered if the detection has been restarted
         let error_code = unsafe { RegisterClassW(&wc) };
-        assert_ne!(error_code, 0, "failed to register the window class");
+        assert!(
+            error_code != 0 || unsafe { GetLastError() } == ERROR_CLASS_ALREADY_EXISTS,
+            "failed to register the window class"
+        );
 
         let window_name: Vec<u16> = OsStr::new("DisplaySwitchPnPDetectWindow")
             .encode_wide()
@@ -0,0 +1,153 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::usb::UsbDevice;
+
+/// Directory, relative to the sysfs root, with an entry for every USB device and interface
+const USB_DEVICES_DIR: &str = "bus/usb/devices";
+
+fn read_attribute(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+/// Read the identity of a USB device from its sysfs directory, e.g. "/sys/bus/usb/devices/1-3.2".
+/// Returns `None` for USB interfaces and for devices that are gone.
+pub fn read_device(sysfs_root: &Path, name: &str) -> Option<UsbDevice> {
+    let dir = sysfs_root.join(USB_DEVICES_DIR).join(name);
+    let vendor_id = u16::from_str_radix(&read_attribute(&dir, "idVendor")?, 16).ok()?;
+    let product_id = u16::from_str_radix(&read_attribute(&dir, "idProduct")?, 16).ok()?;
+    // Root hubs are named "usbN", and have no port chain
+    let port_path = match name.strip_prefix("usb") {
+        Some(_) => read_attribute(&dir, "busnum"),
+        None => Some(name.to_owned()),
+    };
+    // Entries in "bus/usb/devices" are symlinks to the device's directory in the device tree
+    let devpath = fs::canonicalize(&dir).ok().and_then(|path| {
+        let sysfs_root = fs::canonicalize(sysfs_root).ok()?;
+        path.strip_prefix(sysfs_root)
+            .ok()
+            .map(|path| format!("/{}", path.to_string_lossy()))
+    });
+    Some(UsbDevice {
+        id: format!("{:04x}:{:04x}", vendor_id, product_id),
+        manufacturer: read_attribute(&dir, "manufacturer"),
+        product: read_attribute(&dir, "product"),
+        serial_number: read_attribute(&dir, "serial"),
+        port_path,
+        devpath,
+    })
+}
+
+/// Read all USB devices currently present, by their kernel name (e.g. "1-3.2")
+pub fn read_devices(sysfs_root: &Path) -> Result<HashMap<String, UsbDevice>> {
+    let dir = sysfs_root.join(USB_DEVICES_DIR);
+    let entries = fs::read_dir(&dir).with_context(|| format!("failed to read directory: {:?}", dir))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            read_device(sysfs_root, &name).map(|device| (name, device))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::usb::UsbCallback;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// A fake sysfs tree, in a temporary directory that's removed when dropped
+    pub struct FakeSysfs {
+        pub root: PathBuf,
+        _dir: tempfile::TempDir,
+    }
+
+    impl FakeSysfs {
+        pub fn new(name: &str) -> Self {
+            let dir = tempfile::Builder::new()
+                .prefix(&format!("display-switch-{}-", name))
+                .tempdir()
+                .unwrap();
+            let root = dir.path().to_path_buf();
+            fs::create_dir_all(root.join(USB_DEVICES_DIR)).unwrap();
+            Self { root, _dir: dir }
+        }
+
+        pub fn add_device(&self, name: &str, attributes: &[(&str, &str)]) {
+            let dir = self.root.join(USB_DEVICES_DIR).join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+            }
+        }
+
+        pub fn remove_device(&self, name: &str) {
+            fs::remove_dir_all(self.root.join(USB_DEVICES_DIR).join(name)).unwrap();
+        }
+    }
+
+    /// Records the reported devices, e.g. "+046d:c52b" when added and "-046d:c52b" when removed
+    #[derive(Clone, Default)]
+    pub struct RecordingCallback(Arc<Mutex<Vec<String>>>);
+
+    impl UsbCallback for RecordingCallback {
+        fn device_added(&self, device: &UsbDevice) {
+            self.0.lock().unwrap().push(format!("+{}", device.id));
+        }
+
+        fn device_removed(&self, device: &UsbDevice) {
+            self.0.lock().unwrap().push(format!("-{}", device.id));
+        }
+
+        fn devices_enumerated(&self) {}
+    }
+
+    impl RecordingCallback {
+        pub fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_read_devices() {
+        let sysfs = FakeSysfs::new("read-devices");
+        sysfs.add_device("usb1", &[("idVendor", "1d6b"), ("idProduct", "0002"), ("busnum", "1")]);
+        sysfs.add_device(
+            "1-3.2",
+            &[
+                ("idVendor", "046D"),
+                ("idProduct", "c52b"),
+                ("manufacturer", "Logitech"),
+                ("product", "USB Receiver"),
+                ("serial", "ABC123"),
+            ],
+        );
+        // An interface, not a device
+        sysfs.add_device("1-3.2:1.0", &[("bInterfaceClass", "03")]);
+
+        let devices = read_devices(&sysfs.root).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices["usb1"].id, "1d6b:0002");
+        assert_eq!(devices["usb1"].port_path.as_deref(), Some("1"));
+        let device = &devices["1-3.2"];
+        assert_eq!(device.id, "046d:c52b");
+        assert_eq!(device.manufacturer.as_deref(), Some("Logitech"));
+        assert_eq!(device.product.as_deref(), Some("USB Receiver"));
+        assert_eq!(device.serial_number.as_deref(), Some("ABC123"));
+        assert_eq!(device.port_path.as_deref(), Some("1-3.2"));
+        assert_eq!(device.devpath.as_deref(), Some("/bus/usb/devices/1-3.2"));
+    }
+}
@@ -0,0 +1,307 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use async_io::Timer;
+use futures_lite::{FutureExt, StreamExt};
+use zbus::blocking::fdo::{DBusProxy, ObjectManagerProxy};
+use zbus::blocking::{connection, Connection};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{MatchRule, Message, MessageStream};
+
+use crate::configuration::{BluetoothTriggerConfiguration, SwitchDirection};
+use crate::trigger::{TriggerCallback, TriggerSource, STOP_CHECK_INTERVAL};
+
+const BLUEZ_SERVICE: &str = "org.bluez";
+const BLUEZ_DEVICE_INTERFACE: &str = "org.bluez.Device1";
+
+/// Bluetooth trigger: "connect" while a Bluetooth device is connected, as reported by BlueZ over D-Bus
+pub struct BluetoothTriggerSource {
+    name: String,
+    address: String,
+    dbus_address: Option<String>,
+}
+
+/// The signals about the device's state: its properties changing, it being removed, and BlueZ (re)starting
+fn match_rules() -> zbus::Result<Vec<MatchRule<'static>>> {
+    Ok(vec![
+        MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .path_namespace("/org/bluez")?
+            .arg(0, BLUEZ_DEVICE_INTERFACE)?
+            .build(),
+        MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender(BLUEZ_SERVICE)?
+            .interface("org.freedesktop.DBus.ObjectManager")?
+            .member("InterfacesRemoved")?
+            .build(),
+        MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender("org.freedesktop.DBus")?
+            .interface("org.freedesktop.DBus")?
+            .member("NameOwnerChanged")?
+            .arg(0, BLUEZ_SERVICE)?
+            .build(),
+    ])
+}
+
+/// Wait for the next message, for at most `STOP_CHECK_INTERVAL`: `None` if none has arrived meanwhile, `Some(None)`
+/// if the connection is closed
+fn next_message(messages: &mut MessageStream) -> Option<Option<zbus::Result<Message>>> {
+    async_io::block_on(async { Some(messages.next().await) }.or(async {
+        Timer::after(STOP_CHECK_INTERVAL).await;
+        None
+    }))
+}
+
+impl BluetoothTriggerSource {
+    pub fn new(name: String, config: &BluetoothTriggerConfiguration) -> Self {
+        Self {
+            name,
+            address: config.address.to_uppercase(),
+            dbus_address: config.dbus_address.clone(),
+        }
+    }
+
+    /// BlueZ devices' object paths are "/org/bluez/<adapter>/dev_<address with underscores>"
+    fn is_device_path(&self, path: &str) -> bool {
+        path.starts_with("/org/bluez/") && path.ends_with(&format!("/dev_{}", self.address.replace(':', "_")))
+    }
+
+    fn connect(&self) -> zbus::Result<Connection> {
+        match &self.dbus_address {
+            Some(address) => connection::Builder::address(address.as_str())?.build(),
+            None => Connection::system(),
+        }
+    }
+
+    /// Whether the device is currently connected: BlueZ not running is not an error, the device is not connected then
+    fn read_state(&self, connection: &Connection) -> bool {
+        self.read_connected(connection).unwrap_or_else(|err| {
+            warn!("Cannot get the state of Bluetooth device {}: {:?}", self.address, err);
+            false
+        })
+    }
+
+    /// The device's state after a D-Bus signal, if the signal is about it
+    fn signal_state(&self, connection: &Connection, message: &Message) -> Option<bool> {
+        let header = message.header();
+        if header.message_type() != zbus::message::Type::Signal {
+            return None;
+        }
+        let is_device_path = header.path().is_some_and(|path| self.is_device_path(path.as_str()));
+        match (header.interface()?.as_str(), header.member()?.as_str()) {
+            ("org.freedesktop.DBus.Properties", "PropertiesChanged") if is_device_path => {
+                let changed_properties = message
+                    .body()
+                    .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                    .map(|(_, changed_properties, _)| changed_properties);
+                match changed_properties {
+                    Ok(properties) => properties
+                        .get("Connected")
+                        .and_then(|value| value.downcast_ref::<bool>().ok()),
+                    Err(err) => {
+                        warn!("Unexpected Bluetooth device properties change: {:?}", err);
+                        None
+                    }
+                }
+            }
+            // The device was removed, e.g. unpaired
+            ("org.freedesktop.DBus.ObjectManager", "InterfacesRemoved") => {
+                let (path, _) = message.body().deserialize::<(OwnedObjectPath, Vec<String>)>().ok()?;
+                self.is_device_path(path.as_str()).then(|| self.read_state(connection))
+            }
+            // BlueZ was started, stopped or restarted: its devices' states may all have changed
+            ("org.freedesktop.DBus", "NameOwnerChanged") => {
+                let (name, _, _) = message.body().deserialize::<(String, String, String)>().ok()?;
+                (name == BLUEZ_SERVICE).then(|| self.read_state(connection))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the device is currently connected, according to BlueZ
+    fn read_connected(&self, connection: &Connection) -> Result<bool> {
+        let object_manager = ObjectManagerProxy::builder(connection)
+            .destination(BLUEZ_SERVICE)?
+            .path("/")?
+            .build()?;
+        let objects = object_manager.get_managed_objects()?;
+        Ok(objects
+            .iter()
+            .filter(|(path, _)| self.is_device_path(path.as_str()))
+            .flat_map(|(_, interfaces)| interfaces.iter())
+            .filter(|(interface, _)| interface.as_str() == BLUEZ_DEVICE_INTERFACE)
+            .filter_map(|(_, properties)| properties.get("Connected")?.downcast_ref::<bool>().ok())
+            .any(|connected| connected))
+    }
+}
+
+impl TriggerSource for BluetoothTriggerSource {
+    fn name(&self) -> &'static str {
+        "Bluetooth device monitoring"
+    }
+
+    fn triggers(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn run(self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()> {
+        let connection = self.connect().context("failed to connect to D-Bus")?;
+        // Subscribe before reading the current state, so that no change is missed
+        let mut messages = MessageStream::from(connection.inner());
+        let dbus = DBusProxy::new(&connection)?;
+        for rule in match_rules()? {
+            dbus.add_match_rule(rule)?;
+        }
+
+        let mut connected = self.read_state(&connection);
+        info!(
+            "Bluetooth device {} of trigger {:?} is {}",
+            self.address,
+            self.name,
+            if connected { "connected" } else { "disconnected" }
+        );
+        callback.initial_state(&self.name, SwitchDirection::from(connected));
+
+        while !callback.stopped() {
+            let message = match next_message(&mut messages) {
+                Some(Some(message)) => message.context("failed to receive D-Bus messages")?,
+                Some(None) => return Err(anyhow!("D-Bus connection closed")),
+                None => continue,
+            };
+            match self.signal_state(&connection, &message) {
+                Some(new_state) if new_state != connected => connected = new_state,
+                _ => continue,
+            }
+            info!(
+                "Bluetooth device {} of trigger {:?} is {}",
+                self.address,
+                self.name,
+                if connected { "connected" } else { "disconnected" }
+            );
+            callback.state_updated(&self.name, SwitchDirection::from(connected));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trigger::tests::RecordingCallback;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+    use zbus::zvariant::Value;
+
+    const DEVICE_PATH: &str = "/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF";
+
+    /// A private D-Bus session bus, stopped when dropped
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_owned(),
+            })
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    struct MockDevice;
+
+    #[zbus::interface(name = "org.bluez.Device1")]
+    impl MockDevice {
+        #[zbus(property)]
+        fn address(&self) -> String {
+            "AA:BB:CC:DD:EE:FF".into()
+        }
+
+        #[zbus(property)]
+        fn connected(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with `cargo test -- --ignored`"]
+    fn test_mock_bluez() {
+        let bus = PrivateBus::start().expect("failed to start dbus-daemon");
+        let bluez = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(BLUEZ_SERVICE)
+            .unwrap()
+            .serve_at("/", zbus::fdo::ObjectManager)
+            .unwrap()
+            .serve_at(DEVICE_PATH, MockDevice)
+            .unwrap()
+            .build()
+            .unwrap();
+        let config = BluetoothTriggerConfiguration {
+            address: "aa:bb:cc:dd:ee:ff".into(),
+            dbus_address: Some(bus.address.clone()),
+        };
+        let source = Box::new(BluetoothTriggerSource::new("keyboard".into(), &config));
+        let (callback, receiver) = RecordingCallback::new();
+        thread::spawn(move || source.run(callback));
+        let next_event = || receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(next_event(), "keyboard initially connect");
+
+        let emit_connected = |path: &str, connected: bool| {
+            let changed_properties = HashMap::from([("Connected", Value::from(connected))]);
+            bluez
+                .emit_signal(
+                    None::<()>,
+                    path,
+                    "org.freedesktop.DBus.Properties",
+                    "PropertiesChanged",
+                    &(BLUEZ_DEVICE_INTERFACE, changed_properties, Vec::<String>::new()),
+                )
+                .unwrap();
+        };
+        // Another device's changes are ignored
+        emit_connected("/org/bluez/hci0/dev_11_22_33_44_55_66", false);
+        emit_connected(DEVICE_PATH, false);
+        assert_eq!(next_event(), "keyboard disconnect");
+        emit_connected(DEVICE_PATH, true);
+        assert_eq!(next_event(), "keyboard connect");
+
+        // The device is removed
+        assert!(bluez.object_server().remove::<MockDevice, _>(DEVICE_PATH).unwrap());
+        assert_eq!(next_event(), "keyboard disconnect");
+
+        // BlueZ is restarted, and the device is connected again
+        bluez.object_server().at(DEVICE_PATH, MockDevice).unwrap();
+        bluez.release_name(BLUEZ_SERVICE).unwrap();
+        bluez.request_name(BLUEZ_SERVICE).unwrap();
+        assert_eq!(next_event(), "keyboard connect");
+    }
+}
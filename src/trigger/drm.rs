@@ -0,0 +1,153 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::configuration::{DrmTriggerConfiguration, SwitchDirection};
+use crate::trigger::{TriggerCallback, TriggerSource};
+
+/// DRM trigger: "connect" while a display is connected to a given video output of any graphics card, as
+/// reported in "/sys/class/drm/card<N>-<connector>/status"
+pub struct DrmTriggerSource {
+    name: String,
+    connector: String,
+    drm_dir: PathBuf,
+    interval: Duration,
+    connected: Option<bool>,
+}
+
+/// Whether a "/sys/class/drm" entry is the given connector, e.g. "card0-DP-1" for "DP-1". The card can be
+/// included in the connector's name, to tell apart identical outputs of different cards.
+fn is_connector(entry: &str, connector: &str) -> bool {
+    if entry.eq_ignore_ascii_case(connector) {
+        return true;
+    }
+    entry
+        .strip_prefix("card")
+        .map(|s| s.trim_start_matches(|c: char| c.is_ascii_digit()))
+        .and_then(|s| s.strip_prefix('-'))
+        .is_some_and(|name| name.eq_ignore_ascii_case(connector))
+}
+
+impl DrmTriggerSource {
+    pub fn new(name: String, config: &DrmTriggerConfiguration, sysfs_root: &Path) -> Self {
+        Self {
+            name,
+            connector: config.connector.clone(),
+            drm_dir: sysfs_root.join("class/drm"),
+            interval: config.poll_interval,
+            connected: None,
+        }
+    }
+
+    /// Whether a display is connected to the connector on any of the cards
+    fn read_connected(&self) -> bool {
+        let entries = match fs::read_dir(&self.drm_dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                debug!("Cannot read {:?}: {:?}", self.drm_dir, err);
+                return false;
+            }
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_connector(&entry.file_name().to_string_lossy(), &self.connector))
+            .filter_map(|entry| fs::read_to_string(entry.path().join("status")).ok())
+            .any(|status| status.trim() == "connected")
+    }
+
+    fn state(&self) -> SwitchDirection {
+        match self.connected {
+            Some(true) => SwitchDirection::Connect,
+            _ => SwitchDirection::Disconnect,
+        }
+    }
+
+    /// Read the connector's status, and return the trigger's state if it has changed
+    fn poll(&mut self) -> Option<SwitchDirection> {
+        let connected = Some(self.read_connected());
+        if connected == self.connected {
+            return None;
+        }
+        self.connected = connected;
+        info!(
+            "Display connector {:?} of trigger {:?} is {}",
+            self.connector,
+            self.name,
+            if connected == Some(true) {
+                "connected"
+            } else {
+                "disconnected"
+            }
+        );
+        Some(self.state())
+    }
+}
+
+impl TriggerSource for DrmTriggerSource {
+    fn name(&self) -> &'static str {
+        "Display connector monitoring"
+    }
+
+    fn triggers(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn run(mut self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()> {
+        self.poll();
+        callback.initial_state(&self.name, self.state());
+        while !callback.stopped() {
+            thread::sleep(self.interval);
+            if let Some(state) = self.poll() {
+                callback.state_updated(&self.name, state);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connector_names() {
+        assert!(is_connector("card0-DP-1", "DP-1"));
+        assert!(is_connector("card12-HDMI-A-1", "hdmi-a-1"));
+        assert!(is_connector("card1-DP-1", "card1-DP-1"));
+        assert!(!is_connector("card0-DP-1", "card1-DP-1"));
+        assert!(!is_connector("card0-DP-10", "DP-1"));
+        assert!(!is_connector("card0", "DP-1"));
+    }
+
+    #[test]
+    fn test_connector_status_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let connector_dir = root.join("class/drm/card1-DP-1");
+        fs::create_dir_all(&connector_dir).unwrap();
+        fs::create_dir_all(root.join("class/drm/card1-DP-2")).unwrap();
+        fs::write(root.join("class/drm/card1-DP-2/status"), "connected\n").unwrap();
+        let config = DrmTriggerConfiguration {
+            connector: "DP-1".into(),
+            poll_interval: Duration::from_secs(1),
+        };
+        let mut source = DrmTriggerSource::new("monitor".into(), &config, root);
+
+        fs::write(connector_dir.join("status"), "disconnected\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Disconnect));
+        fs::write(connector_dir.join("status"), "connected\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Connect));
+        assert_eq!(source.poll(), None);
+        fs::write(connector_dir.join("status"), "disconnected\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Disconnect));
+    }
+}
@@ -0,0 +1,298 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::configuration::{FileTriggerConfiguration, FileTriggerMode, SwitchDirection};
+use crate::trigger::{TriggerCallback, TriggerSource, STOP_CHECK_INTERVAL};
+
+/// Changes in the watched directory that can create or remove the flag file
+const DIRECTORY_EVENTS: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+/// The watched directory itself is gone, no more events will come
+const DIRECTORY_GONE_EVENTS: u32 = libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_IGNORED;
+const INOTIFY_BUFFER_SIZE: usize = 4096;
+
+/// File trigger, for scripts: either "connect" while a flag file exists, or driven by "connect" / "disconnect"
+/// lines written to a named pipe
+pub struct FileTriggerSource {
+    name: String,
+    path: PathBuf,
+    mode: FileTriggerMode,
+}
+
+/// Inotify file descriptor watching a directory
+fn watch_directory(dir: &Path) -> io::Result<File> {
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `inotify_init1` returns a new descriptor that nothing else owns, so `OwnedFd` can take it over, and
+    // `dir` is a NUL-terminated string that lives until after `inotify_add_watch` returns
+    unsafe {
+        let fd = libc::inotify_init1(libc::IN_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+        if libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), DIRECTORY_EVENTS | DIRECTORY_GONE_EVENTS) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from(fd))
+    }
+}
+
+/// Wait until the file has data to read, or `STOP_CHECK_INTERVAL` has passed: returns whether there is data
+fn wait_readable(file: &File) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `poll_fd` is the one pollfd passed, and its descriptor is kept open by `file` during the call
+    match unsafe { libc::poll(&mut poll_fd, 1, STOP_CHECK_INTERVAL.as_millis() as libc::c_int) } {
+        result if result < 0 => Err(io::Error::last_os_error()),
+        result => Ok(result > 0),
+    }
+}
+
+/// Masks of the events read from an inotify file descriptor: each event is a `libc::inotify_event`, followed by
+/// the name of the file it's about
+fn event_masks(buffer: &[u8]) -> Vec<u32> {
+    let header_size = std::mem::size_of::<libc::inotify_event>();
+    let field = |offset: usize| u32::from_ne_bytes(buffer[offset..offset + 4].try_into().unwrap());
+    let mut masks = Vec::new();
+    let mut offset = 0;
+    while offset + header_size <= buffer.len() {
+        // Fields are "wd", "mask", "cookie" and "len"
+        masks.push(field(offset + 4));
+        offset += header_size + field(offset + 12) as usize;
+    }
+    masks
+}
+
+impl FileTriggerSource {
+    pub fn new(name: String, config: &FileTriggerConfiguration) -> Self {
+        Self {
+            name,
+            path: config.path.clone(),
+            mode: config.mode,
+        }
+    }
+
+    fn watch_flag_file(&self, callback: &dyn TriggerCallback) -> Result<()> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut inotify = watch_directory(dir).with_context(|| format!("failed to watch directory {:?}", dir))?;
+        let mut exists = self.path.exists();
+        info!(
+            "Flag file {:?} of trigger {:?} exists: {}",
+            self.path, self.name, exists
+        );
+        callback.initial_state(&self.name, SwitchDirection::from(exists));
+
+        let mut buffer = vec![0u8; INOTIFY_BUFFER_SIZE];
+        while !callback.stopped() {
+            match wait_readable(&inotify) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err).context("failed to wait for inotify events"),
+            }
+            let size = match inotify.read(&mut buffer) {
+                Ok(size) => size,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err).context("failed to read inotify events"),
+            };
+            let masks = event_masks(&buffer[..size]);
+            if masks.iter().any(|mask| mask & DIRECTORY_GONE_EVENTS != 0) {
+                return Err(anyhow!("directory {:?} was removed", dir));
+            }
+            // Events can be about any file in the directory, or have overflowed the queue: just check again
+            if self.path.exists() != exists {
+                exists = !exists;
+                info!(
+                    "Flag file {:?} of trigger {:?} exists: {}",
+                    self.path, self.name, exists
+                );
+                callback.state_updated(&self.name, SwitchDirection::from(exists));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_fifo(&self, callback: &dyn TriggerCallback) -> Result<()> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) if !metadata.file_type().is_fifo() => {
+                return Err(anyhow!("{:?} exists, and is not a named pipe", self.path));
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let path = CString::new(self.path.as_os_str().as_bytes())?;
+                // SAFETY: `path` is a NUL-terminated string that lives until after `mkfifo` returns
+                if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } < 0 {
+                    return Err(io::Error::last_os_error())
+                        .with_context(|| format!("failed to create {:?}", self.path));
+                }
+            }
+            Err(err) => return Err(err).with_context(|| format!("failed to access {:?}", self.path)),
+        }
+        // Opened for writing as well, so that the pipe doesn't reach EOF whenever a writer closes it
+        let fifo = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {:?}", self.path))?;
+        callback.initial_state(&self.name, SwitchDirection::Disconnect);
+
+        let mut reader = BufReader::new(fifo);
+        let mut line = String::new();
+        while !callback.stopped() {
+            // Lines already buffered are handled without waiting
+            if !reader.buffer().contains(&b'\n') {
+                match wait_readable(reader.get_ref()) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", self.path)),
+                }
+            }
+            line.clear();
+            let size = reader
+                .read_line(&mut line)
+                .with_context(|| format!("failed to read {:?}", self.path))?;
+            if size == 0 {
+                return Err(anyhow!("{:?} was closed", self.path));
+            }
+            let direction = match line.trim() {
+                "connect" => SwitchDirection::Connect,
+                "disconnect" => SwitchDirection::Disconnect,
+                "" => continue,
+                command => {
+                    warn!(
+                        "Unknown command {:?} in {:?}, expected \"connect\" or \"disconnect\"",
+                        command, self.path
+                    );
+                    continue;
+                }
+            };
+            info!("Trigger {:?} received {} from {:?}", self.name, direction, self.path);
+            callback.state_updated(&self.name, direction);
+        }
+        Ok(())
+    }
+}
+
+impl TriggerSource for FileTriggerSource {
+    fn name(&self) -> &'static str {
+        "File monitoring"
+    }
+
+    fn triggers(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn run(self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()> {
+        match self.mode {
+            FileTriggerMode::Flag => self.watch_flag_file(callback.as_ref()),
+            FileTriggerMode::Fifo => self.read_fifo(callback.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trigger::tests::RecordingCallback;
+    use std::io::Write;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Start a trigger on a file in a temporary directory, which is removed when dropped
+    fn start(mode: FileTriggerMode) -> (TempDir, Receiver<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FileTriggerConfiguration {
+            path: dir.path().join("trigger"),
+            mode,
+        };
+        let source = Box::new(FileTriggerSource::new("script".into(), &config));
+        let (callback, receiver) = RecordingCallback::new();
+        thread::spawn(move || source.run(callback));
+        (dir, receiver)
+    }
+
+    fn next_event(receiver: &Receiver<String>) -> String {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_flag_file() {
+        let (temp_dir, events) = start(FileTriggerMode::Flag);
+        let dir = temp_dir.path();
+        assert_eq!(next_event(&events), "script initially disconnect");
+        fs::write(dir.join("unrelated"), "").unwrap();
+        fs::write(dir.join("trigger"), "").unwrap();
+        assert_eq!(next_event(&events), "script connect");
+        fs::remove_file(dir.join("trigger")).unwrap();
+        assert_eq!(next_event(&events), "script disconnect");
+    }
+
+    #[test]
+    fn test_fifo() {
+        let (temp_dir, events) = start(FileTriggerMode::Fifo);
+        let dir = temp_dir.path();
+        assert_eq!(next_event(&events), "script initially disconnect");
+        let mut fifo = OpenOptions::new().write(true).open(dir.join("trigger")).unwrap();
+        writeln!(fifo, "connect\nflip\n\ndisconnect").unwrap();
+        assert_eq!(next_event(&events), "script connect");
+        assert_eq!(next_event(&events), "script disconnect");
+    }
+
+    #[test]
+    fn test_stop() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for mode in [FileTriggerMode::Flag, FileTriggerMode::Fifo] {
+            let config = FileTriggerConfiguration {
+                path: temp_dir.path().join("trigger"),
+                mode,
+            };
+            let source = Box::new(FileTriggerSource::new("script".into(), &config));
+            let (callback, events) = RecordingCallback::new();
+            let running = thread::spawn({
+                let callback = callback.clone();
+                move || source.run(callback)
+            });
+            assert_eq!(next_event(&events), "script initially disconnect");
+            callback.stop.store(true, Ordering::SeqCst);
+            assert!(running.join().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_event_masks() {
+        let mut buffer = Vec::new();
+        for (mask, name) in [
+            (libc::IN_CREATE, &b"trigger\0\0\0\0\0\0\0\0\0"[..]),
+            (libc::IN_IGNORED, &b""[..]),
+        ] {
+            buffer.extend_from_slice(&1i32.to_ne_bytes());
+            buffer.extend_from_slice(&mask.to_ne_bytes());
+            buffer.extend_from_slice(&0u32.to_ne_bytes());
+            buffer.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            buffer.extend_from_slice(name);
+        }
+        assert_eq!(event_masks(&buffer), vec![libc::IN_CREATE, libc::IN_IGNORED]);
+    }
+}
@@ -0,0 +1,125 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::configuration::{Configuration, SwitchDirection, TriggerConfiguration};
+
+#[cfg(target_os = "linux")]
+mod bluetooth;
+#[cfg(target_os = "linux")]
+mod drm;
+#[cfg(target_os = "linux")]
+mod file;
+#[cfg(target_os = "linux")]
+mod network;
+mod usb;
+
+/// How often the sources that wait for events check whether they should stop
+pub const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Receives the states of named triggers: "connect" when the trigger's condition is met (e.g. the monitored USB
+/// devices are present), "disconnect" otherwise
+pub trait TriggerCallback: Send + Sync {
+    /// The trigger's state when the source has started, reported once before any update
+    fn initial_state(&self, trigger: &str, state: SwitchDirection);
+    /// The trigger's state after an event, which may or may not have changed it
+    fn state_updated(&self, trigger: &str, state: SwitchDirection);
+    /// Whether the source should stop, e.g. because the trigger settings have changed. Checked between polls, or at
+    /// least every `STOP_CHECK_INTERVAL` while waiting for events.
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+/// Watches for events, and reports the resulting states of one or more triggers
+pub trait TriggerSource: Send {
+    /// Describes the source in error messages, e.g. "USB device detection"
+    fn name(&self) -> &'static str;
+    /// Names of the triggers whose states it reports
+    fn triggers(&self) -> Vec<String>;
+    /// Report the triggers' states to the callback until it's stopped, only returns earlier on failure
+    fn run(self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()>;
+}
+
+/// The sources of the triggers that switch the displays
+pub fn trigger_sources(config: &Configuration) -> Vec<Box<dyn TriggerSource>> {
+    let mut sources: Vec<Box<dyn TriggerSource>> = Vec::new();
+    let mut usb_triggers = Vec::new();
+    for (name, trigger) in config.active_triggers() {
+        match trigger {
+            TriggerConfiguration::Usb(usb) => usb_triggers.push((name, usb)),
+            #[cfg(target_os = "linux")]
+            TriggerConfiguration::Network(network) => {
+                let source = network::NetworkTriggerSource::new(name, &network, &config.general.sysfs_root);
+                sources.push(Box::new(source));
+            }
+            #[cfg(target_os = "linux")]
+            TriggerConfiguration::Drm(drm) => {
+                sources.push(Box::new(drm::DrmTriggerSource::new(
+                    name,
+                    &drm,
+                    &config.general.sysfs_root,
+                )));
+            }
+            #[cfg(target_os = "linux")]
+            TriggerConfiguration::Bluetooth(bluetooth) => {
+                sources.push(Box::new(bluetooth::BluetoothTriggerSource::new(name, &bluetooth)));
+            }
+            #[cfg(target_os = "linux")]
+            TriggerConfiguration::File(file) => {
+                sources.push(Box::new(file::FileTriggerSource::new(name, &file)));
+            }
+        }
+    }
+    // A single USB detector serves all the USB triggers
+    if !usb_triggers.is_empty() {
+        sources.push(Box::new(usb::UsbTriggerSource::new(config, usb_triggers)));
+    }
+    sources
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    /// Sends the reported states as text, e.g. "dock initially connect" or "dock disconnect"
+    pub struct RecordingCallback {
+        sender: Sender<String>,
+        /// Tells the source to stop
+        pub stop: AtomicBool,
+    }
+
+    impl RecordingCallback {
+        /// The callback, and the receiver of the states reported to it
+        pub fn new() -> (Arc<Self>, Receiver<String>) {
+            let (sender, receiver) = mpsc::channel();
+            let callback = Self {
+                sender,
+                stop: AtomicBool::new(false),
+            };
+            (Arc::new(callback), receiver)
+        }
+    }
+
+    impl TriggerCallback for RecordingCallback {
+        fn initial_state(&self, trigger: &str, state: SwitchDirection) {
+            let _ = self.sender.send(format!("{} initially {}", trigger, state));
+        }
+
+        fn state_updated(&self, trigger: &str, state: SwitchDirection) {
+            let _ = self.sender.send(format!("{} {}", trigger, state));
+        }
+
+        fn stopped(&self) -> bool {
+            self.stop.load(Ordering::SeqCst)
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::configuration::{NetworkTriggerConfiguration, SwitchDirection};
+use crate::trigger::{TriggerCallback, TriggerSource};
+
+/// Network trigger: "connect" while a network interface's link is up, e.g. a dock's USB Ethernet adapter.
+/// Polls the interface's "operstate" in sysfs; an interface that doesn't exist (anymore) is "disconnected".
+pub struct NetworkTriggerSource {
+    name: String,
+    interface: String,
+    operstate_path: PathBuf,
+    interval: Duration,
+    /// The interface's state during the last poll, as reported by the kernel
+    operstate: Option<String>,
+}
+
+impl NetworkTriggerSource {
+    pub fn new(name: String, config: &NetworkTriggerConfiguration, sysfs_root: &Path) -> Self {
+        Self {
+            name,
+            interface: config.interface.clone(),
+            operstate_path: sysfs_root.join("class/net").join(&config.interface).join("operstate"),
+            interval: config.poll_interval,
+            operstate: None,
+        }
+    }
+
+    fn state(&self) -> SwitchDirection {
+        match self.operstate.as_deref() {
+            Some("up") => SwitchDirection::Connect,
+            _ => SwitchDirection::Disconnect,
+        }
+    }
+
+    /// Read the interface's state, and return the trigger's state if it has changed
+    fn poll(&mut self) -> Option<SwitchDirection> {
+        let operstate = fs::read_to_string(&self.operstate_path)
+            .ok()
+            .map(|s| s.trim().to_owned());
+        if operstate == self.operstate {
+            return None;
+        }
+        info!(
+            "Network interface {:?} of trigger {:?} is {}",
+            self.interface,
+            self.name,
+            operstate.as_deref().unwrap_or("absent")
+        );
+        let previous_state = self.state();
+        self.operstate = operstate;
+        Some(self.state()).filter(|state| *state != previous_state)
+    }
+}
+
+impl TriggerSource for NetworkTriggerSource {
+    fn name(&self) -> &'static str {
+        "Network interface monitoring"
+    }
+
+    fn triggers(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+
+    fn run(mut self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()> {
+        self.poll();
+        callback.initial_state(&self.name, self.state());
+        while !callback.stopped() {
+            thread::sleep(self.interval);
+            if let Some(state) = self.poll() {
+                callback.state_updated(&self.name, state);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_state_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("class/net/eth1");
+        fs::create_dir_all(&dir).unwrap();
+        let config = NetworkTriggerConfiguration {
+            interface: "eth1".into(),
+            poll_interval: Duration::from_secs(1),
+        };
+        let mut source = NetworkTriggerSource::new("dock".into(), &config, root.path());
+
+        fs::write(dir.join("operstate"), "down\n").unwrap();
+        assert_eq!(source.poll(), None);
+        fs::write(dir.join("operstate"), "up\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Connect));
+        assert_eq!(source.poll(), None);
+        fs::write(dir.join("operstate"), "lowerlayerdown\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Disconnect));
+        fs::write(dir.join("operstate"), "up\n").unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Connect));
+        // The adapter is unplugged
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(source.poll(), Some(SwitchDirection::Disconnect));
+    }
+}
@@ -0,0 +1,262 @@
+//
+// Copyright © 2020 Haim Gelfenbeyn
+// This code is licensed under MIT license (see LICENSE.txt for details)
+//
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use crate::configuration::{Configuration, SwitchDirection, UsbTriggerConfiguration};
+use crate::platform::detect_usb_devices;
+use crate::trigger::{TriggerCallback, TriggerSource};
+use crate::usb::{self, UsbCallback, UsbDevice};
+
+/// USB triggers: "connect" when their monitored USB devices are present, as reported by the USB detector
+pub struct UsbTriggerSource {
+    config: Configuration,
+    triggers: Vec<(String, UsbTriggerConfiguration)>,
+}
+
+impl UsbTriggerSource {
+    pub fn new(config: &Configuration, triggers: Vec<(String, UsbTriggerConfiguration)>) -> Self {
+        Self {
+            config: config.clone(),
+            triggers,
+        }
+    }
+}
+
+impl TriggerSource for UsbTriggerSource {
+    fn name(&self) -> &'static str {
+        "USB device detection"
+    }
+
+    fn triggers(&self) -> Vec<String> {
+        self.triggers.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn run(self: Box<Self>, callback: Arc<dyn TriggerCallback>) -> Result<()> {
+        detect_usb_devices(&self.config, Box::new(UsbTriggers::new(self.triggers, callback)))
+    }
+}
+
+struct UsbTrigger {
+    name: String,
+    config: UsbTriggerConfiguration,
+    present_devices: Mutex<PresentDevices>,
+}
+
+#[derive(Default)]
+struct PresentDevices {
+    /// Number of currently connected instances of each monitored device
+    counts: HashMap<String, usize>,
+    /// IDs of the monitored hubs, by port path, with `usb_hub_subtree` (devices without a port path are counted)
+    hubs: HashMap<String, String>,
+}
+
+impl UsbTrigger {
+    fn state(&self, present_devices: &PresentDevices) -> SwitchDirection {
+        let present_ids = present_devices
+            .counts
+            .keys()
+            .chain(present_devices.hubs.values())
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        SwitchDirection::from(self.config.is_usb_connected(&present_ids))
+    }
+
+    /// Update the present devices after a monitored device was added or removed, return whether that matters
+    fn update(&self, present_devices: &mut PresentDevices, device: &UsbDevice, added: bool) -> bool {
+        match (self.config.usb_hub_subtree, &device.port_path) {
+            (true, Some(port_path)) => {
+                let hubs = &mut present_devices.hubs;
+                if hubs.keys().any(|hub| usb::is_behind_hub(port_path, hub)) {
+                    debug!(
+                        "Device ({}) is behind a monitored hub of trigger {:?}, ignoring",
+                        device, self.name
+                    );
+                    return false;
+                }
+                if added {
+                    // Hubs' devices can be enumerated before the hubs themselves at startup
+                    hubs.retain(|hub, _| !usb::is_behind_hub(hub, port_path));
+                    hubs.insert(port_path.clone(), device.id.clone());
+                } else if hubs.remove(port_path).is_none() {
+                    return false;
+                }
+            }
+            _ => {
+                let counts = &mut present_devices.counts;
+                if added {
+                    *counts.entry(device.id.clone()).or_default() += 1;
+                } else if let Some(count) = counts.get_mut(&device.id) {
+                    *count -= 1;
+                    if *count == 0 {
+                        counts.remove(&device.id);
+                    }
+                }
+            }
+        }
+        // The trigger's state is logged when the displays are switched, once the events have been debounced
+        debug!(
+            "Monitored device ({}) of trigger {:?} is {}",
+            device,
+            self.name,
+            if added { "connected" } else { "disconnected" }
+        );
+        true
+    }
+}
+
+/// Turns USB device events into the states of the USB triggers
+struct UsbTriggers {
+    triggers: Vec<UsbTrigger>,
+    callback: Arc<dyn TriggerCallback>,
+    /// Whether the devices present at startup have all been reported
+    enumerated: AtomicBool,
+}
+
+impl UsbTriggers {
+    fn new(triggers: Vec<(String, UsbTriggerConfiguration)>, callback: Arc<dyn TriggerCallback>) -> Self {
+        Self {
+            triggers: triggers
+                .into_iter()
+                .map(|(name, config)| UsbTrigger {
+                    name,
+                    config,
+                    present_devices: Mutex::new(PresentDevices::default()),
+                })
+                .collect(),
+            callback,
+            enumerated: AtomicBool::new(false),
+        }
+    }
+
+    fn device_changed(&self, device: &UsbDevice, added: bool) {
+        for trigger in self.triggers.iter().filter(|t| t.config.matches_usb_device(device)) {
+            let mut present_devices = trigger.present_devices.lock().unwrap();
+            if trigger.update(&mut present_devices, device, added) && self.enumerated.load(Ordering::SeqCst) {
+                self.callback
+                    .state_updated(&trigger.name, trigger.state(&present_devices));
+            }
+        }
+    }
+}
+
+impl UsbCallback for UsbTriggers {
+    fn device_added(&self, device: &UsbDevice) {
+        debug!("Detected device change. Added device: {}", device);
+        self.device_changed(device, true);
+    }
+
+    fn device_removed(&self, device: &UsbDevice) {
+        debug!("Detected device change. Removed device: {}", device);
+        self.device_changed(device, false);
+    }
+
+    fn devices_enumerated(&self) {
+        if self.enumerated.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for trigger in self.triggers.iter() {
+            let present_devices = trigger.present_devices.lock().unwrap();
+            self.callback
+                .initial_state(&trigger.name, trigger.state(&present_devices));
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.callback.stopped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trigger::tests::RecordingCallback;
+
+    fn device(id: &str) -> UsbDevice {
+        UsbDevice {
+            id: id.into(),
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            port_path: None,
+            devpath: None,
+        }
+    }
+
+    fn device_at(id: &str, port_path: &str) -> UsbDevice {
+        UsbDevice {
+            port_path: Some(port_path.into()),
+            ..device(id)
+        }
+    }
+
+    #[test]
+    fn test_usb_trigger_states() {
+        let config = |devices: &str| UsbTriggerConfiguration {
+            usb_device: devices.split(',').map(|d| d.parse().unwrap()).collect(),
+            ..Default::default()
+        };
+        let (callback, events) = RecordingCallback::new();
+        let triggers = UsbTriggers::new(
+            vec![
+                ("keyboard".into(), config("046d:c52b")),
+                ("yubikey".into(), config("1050:0407")),
+            ],
+            callback,
+        );
+
+        triggers.device_added(&device("046d:c52b"));
+        triggers.device_added(&device("dead:beef"));
+        triggers.devices_enumerated();
+        triggers.device_added(&device("1050:0407"));
+        // Another instance of the same device is still present
+        triggers.device_added(&device("046d:c52b"));
+        triggers.device_removed(&device("046d:c52b"));
+        triggers.device_removed(&device("046d:c52b"));
+        assert_eq!(
+            events.try_iter().collect::<Vec<String>>(),
+            vec![
+                "keyboard initially connect",
+                "yubikey initially disconnect",
+                "yubikey connect",
+                "keyboard connect",
+                "keyboard connect",
+                "keyboard disconnect",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usb_hub_subtree() {
+        let config = UsbTriggerConfiguration {
+            usb_device: vec!["05e3:*".parse().unwrap()],
+            usb_hub_subtree: true,
+            ..Default::default()
+        };
+        let (callback, events) = RecordingCallback::new();
+        let triggers = UsbTriggers::new(vec![("switch".into(), config)], callback);
+        triggers.devices_enumerated();
+
+        // The switch's hub, with another hub and a device behind it
+        triggers.device_added(&device_at("05e3:0610", "1-3"));
+        triggers.device_added(&device_at("05e3:0626", "1-3.1"));
+        triggers.device_added(&device_at("05e3:0749", "1-3.1.2"));
+        triggers.device_removed(&device_at("05e3:0749", "1-3.1.2"));
+        triggers.device_added(&device_at("05e3:0749", "1-3.1.2"));
+        triggers.device_removed(&device_at("05e3:0749", "1-3.1.2"));
+        triggers.device_removed(&device_at("05e3:0626", "1-3.1"));
+        triggers.device_removed(&device_at("05e3:0610", "1-3"));
+        // Devices behind the hub that are reported after it is gone
+        triggers.device_removed(&device_at("05e3:0626", "1-3.1"));
+        assert_eq!(
+            events.try_iter().collect::<Vec<String>>(),
+            vec!["switch initially disconnect", "switch connect", "switch disconnect"]
+        );
+    }
+}
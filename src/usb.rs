@@ -2,7 +2,71 @@
 // Copyright © 2020 Haim Gelfenbeyn
 // This code is licensed under MIT license (see LICENSE.txt for details)
 //
+use anyhow::anyhow;
 use rusb::UsbContext;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Identity of a USB device, as reported to `UsbCallback`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct UsbDevice {
+    /// Vendor ID / product ID, as formatted by `device_id`
+    pub id: String,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Physical location of the device: bus number and the chain of hub ports, e.g. "1-3.2"
+    pub port_path: Option<String>,
+    /// Kernel device path, e.g. "/devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3.2" (Linux uevent detector only)
+    pub devpath: Option<String>,
+}
+
+impl UsbDevice {
+    pub fn from_device<T: UsbContext>(device: &rusb::Device<T>) -> Option<Self> {
+        let id = device_id(device)?;
+        let descriptor = device.device_descriptor().ok()?;
+        // Reading the string descriptors requires opening the device, which is not always permitted:
+        // the device is still reported, just without these strings.
+        let handle = device.open().ok();
+        let read_string = |read: fn(&rusb::DeviceHandle<T>, &rusb::DeviceDescriptor) -> rusb::Result<String>| {
+            handle
+                .as_ref()
+                .and_then(|handle| read(handle, &descriptor).ok())
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+        };
+        Some(Self {
+            id,
+            manufacturer: read_string(rusb::DeviceHandle::read_manufacturer_string_ascii),
+            product: read_string(rusb::DeviceHandle::read_product_string_ascii),
+            serial_number: read_string(rusb::DeviceHandle::read_serial_number_string_ascii),
+            port_path: port_path(device),
+            devpath: None,
+        })
+    }
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        let names = [&self.manufacturer, &self.product]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>();
+        if !names.is_empty() {
+            write!(f, " ({})", names.join(" "))?;
+        }
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, " serial {:?}", serial_number)?;
+        }
+        if let Some(port_path) = &self.port_path {
+            write!(f, " at {}", port_path)?;
+        }
+        Ok(())
+    }
+}
 
 pub fn device_id<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
     device
@@ -11,19 +75,169 @@ pub fn device_id<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
         .ok()
 }
 
-// pub fn device_product_name<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
-//     let descriptor = device.device_descriptor().ok()?;
-//     let handle = device.open().ok()?;
-//     handle.read_product_string_ascii(&descriptor).ok()
-// }
-//
-// pub fn device_manufacturer_name<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
-//     let descriptor = device.device_descriptor().ok()?;
-//     let handle = device.open().ok()?;
-//     handle.read_manufacturer_string_ascii(&descriptor).ok()
-// }
-
-pub trait UsbCallback: Send {
-    fn device_added(&self, device_id: &str);
-    fn device_removed(&self, device_id: &str);
+/// All the USB devices currently connected, ordered by their physical location
+pub fn connected_devices() -> rusb::Result<Vec<UsbDevice>> {
+    let mut devices = rusb::devices()?
+        .iter()
+        .filter_map(|device| UsbDevice::from_device(&device))
+        .collect::<Vec<UsbDevice>>();
+    devices.sort_by(|a, b| a.port_path.cmp(&b.port_path).then_with(|| a.id.cmp(&b.id)));
+    Ok(devices)
+}
+
+/// Physical location of the device in the same format Linux uses in sysfs: "<bus>-<port>.<port>...".
+/// Root hubs have no port chain, and are identified by the bus number only.
+pub fn port_path<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
+    let ports = device.port_numbers().ok()?;
+    if ports.is_empty() {
+        return Some(device.bus_number().to_string());
+    }
+    let ports = ports.iter().map(|port| port.to_string()).collect::<Vec<String>>();
+    Some(format!("{}-{}", device.bus_number(), ports.join(".")))
+}
+
+/// Whether a device's port path (as formatted by `port_path`) is behind the hub at `hub_port_path`
+pub fn is_behind_hub(port_path: &str, hub_port_path: &str) -> bool {
+    match port_path.strip_prefix(hub_port_path) {
+        // Devices plugged into a root hub ("1") are "1-<port>", and further hubs add ".<port>"
+        Some(rest) if hub_port_path.contains('-') => rest.starts_with('.'),
+        Some(rest) => rest.starts_with('-'),
+        None => false,
+    }
+}
+
+/// A configured vendor ID / product ID pair, e.g. "046d:c52b". Either part can be a "*" wildcard,
+/// so that "046d:*" matches any device made by that vendor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsbDeviceMatcher {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+}
+
+impl UsbDeviceMatcher {
+    /// Whether a device ID (as formatted by `device_id`) matches this pattern
+    pub fn matches_id(&self, device_id: &str) -> bool {
+        fn matches(expected: Option<u16>, actual: &str) -> bool {
+            expected.is_none_or(|expected| u16::from_str_radix(actual, 16) == Ok(expected))
+        }
+
+        match device_id.split_once(':') {
+            Some((vendor_id, product_id)) => matches(self.vendor_id, vendor_id) && matches(self.product_id, product_id),
+            None => false,
+        }
+    }
+}
+
+impl FromStr for UsbDeviceMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_part(part: &str) -> Option<Option<u16>> {
+            match part {
+                "*" => Some(None),
+                _ if part.len() == 4 && part.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    u16::from_str_radix(part, 16).ok().map(Some)
+                }
+                _ => None,
+            }
+        }
+
+        let s = s.trim();
+        s.split_once(':')
+            .and_then(|(vendor_id, product_id)| {
+                Some(Self {
+                    vendor_id: parse_part(vendor_id)?,
+                    product_id: parse_part(product_id)?,
+                })
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid USB device ID {:?}: expected a 4-digit hex vendor ID and product ID separated by a colon \
+                    (e.g. \"046d:c52b\"), either can be \"*\"",
+                    s
+                )
+            })
+    }
+}
+
+impl fmt::Display for UsbDeviceMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn fmt_part(f: &mut fmt::Formatter<'_>, part: Option<u16>) -> fmt::Result {
+            match part {
+                Some(id) => write!(f, "{:04x}", id),
+                None => write!(f, "*"),
+            }
+        }
+
+        fmt_part(f, self.vendor_id)?;
+        write!(f, ":")?;
+        fmt_part(f, self.product_id)
+    }
+}
+
+impl<'de> Deserialize<'de> for UsbDeviceMatcher {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+pub trait UsbCallback: Send + Sync {
+    fn device_added(&self, device: &UsbDevice);
+    fn device_removed(&self, device: &UsbDevice);
+    /// All the devices present when the detection has started have been reported as added
+    fn devices_enumerated(&self);
+    /// Whether the detection should stop, e.g. because the trigger settings have changed
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_device_matcher_parsing() {
+        assert_eq!(
+            "DEAD:beef".parse::<UsbDeviceMatcher>().unwrap().to_string(),
+            "dead:beef"
+        );
+        assert_eq!("046d:*".parse::<UsbDeviceMatcher>().unwrap().to_string(), "046d:*");
+        assert_eq!("*:c52b".parse::<UsbDeviceMatcher>().unwrap().to_string(), "*:c52b");
+        assert!("1050-0407".parse::<UsbDeviceMatcher>().is_err());
+        assert!("1050:407".parse::<UsbDeviceMatcher>().is_err());
+        assert!("1050:04070".parse::<UsbDeviceMatcher>().is_err());
+        assert!("105g:0407".parse::<UsbDeviceMatcher>().is_err());
+        assert!("".parse::<UsbDeviceMatcher>().is_err());
+    }
+
+    #[test]
+    fn test_is_behind_hub() {
+        assert!(is_behind_hub("1-3", "1"));
+        assert!(is_behind_hub("1-3.2", "1-3"));
+        assert!(is_behind_hub("1-3.2.4", "1-3"));
+        assert!(!is_behind_hub("1-3", "1-3"));
+        assert!(!is_behind_hub("1-30", "1-3"));
+        assert!(!is_behind_hub("11-3", "1"));
+        assert!(!is_behind_hub("2-3", "1"));
+    }
+
+    #[test]
+    fn test_usb_device_matcher_wildcards() {
+        let matcher: UsbDeviceMatcher = "046d:c52b".parse().unwrap();
+        assert!(matcher.matches_id("046d:c52b"));
+        assert!(!matcher.matches_id("046d:c077"));
+        let matcher: UsbDeviceMatcher = "046d:*".parse().unwrap();
+        assert!(matcher.matches_id("046d:c52b"));
+        assert!(matcher.matches_id("046d:c077"));
+        assert!(!matcher.matches_id("1050:0407"));
+        let matcher: UsbDeviceMatcher = "*:c52b".parse().unwrap();
+        assert!(matcher.matches_id("046d:c52b"));
+        assert!(!matcher.matches_id("046d:c077"));
+    }
 }